@@ -0,0 +1,166 @@
+use super::{timestamp, ParsedCertificate};
+use anyhow::Result as AnyResult;
+use bcder::{Mode, Tag};
+use rustls_pki_types::CertificateRevocationListDer;
+use webpki::{CertRevocationList, OwnedCertRevocationList};
+use x509_certificate::{asn1time::Time, rfc3280::Name, rfc5280::AlgorithmIdentifier};
+
+/// A certificate revocation list loaded from a local file
+#[derive(Debug)]
+pub struct CertificateRevocationList {
+    der: CertificateRevocationListDer<'static>,
+    crl: CertRevocationList<'static>,
+    issuer: Name,
+    /// The path of the file, or empty if the list is given inline
+    pub file: String,
+    pub this_update: i64,
+    pub next_update: Option<i64>,
+}
+
+impl CertificateRevocationList {
+    pub fn from_der(der: CertificateRevocationListDer<'static>) -> AnyResult<Self> {
+        let crl = OwnedCertRevocationList::from_der(der.as_ref())?.into();
+
+        // webpki doesn't expose the update times, so parse the beginning of the TBSCertList
+        let (issuer, this_update, next_update) = Mode::Der.decode(der.as_ref(), |cons| {
+            cons.take_sequence(|cons| {
+                let fields = cons.take_sequence(|cons| {
+                    // version
+                    cons.take_opt_primitive_if(Tag::INTEGER, |prim| prim.skip_all())?;
+                    // signature
+                    AlgorithmIdentifier::take_from(cons)?;
+                    let issuer = Name::take_from(cons)?;
+                    let this_update = Time::take_from(cons)?;
+                    let next_update = Time::take_opt_from(cons)?;
+                    cons.skip_all()?;
+                    Ok((issuer, this_update, next_update))
+                })?;
+                cons.skip_all()?;
+                Ok(fields)
+            })
+        })?;
+
+        Ok(Self {
+            der,
+            crl,
+            issuer,
+            file: String::new(),
+            this_update: timestamp(&this_update),
+            next_update: next_update.as_ref().map(timestamp),
+        })
+    }
+
+    pub fn der(&self) -> &CertificateRevocationListDer<'static> {
+        &self.der
+    }
+
+    pub fn issuer_common_name(&self) -> Option<String> {
+        self.issuer
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.to_string().ok())
+    }
+
+    /// Whether the certificate is revoked, or `None` if the list isn't issued by its issuer.
+    pub fn is_revoked(&self, cert: &ParsedCertificate) -> Option<bool> {
+        if cert.issuer_name() != &self.issuer {
+            return None;
+        }
+        self.crl
+            .find_serial(cert.serial_number_asn1().as_slice())
+            .map(|revoked| revoked.is_some())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bcder::encode::Values;
+    use x509_certificate::{KeyAlgorithm, X509CertificateBuilder};
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => der.push(len as u8),
+            len => der.extend([0x81, len as u8]),
+        }
+        der.extend(content);
+        der
+    }
+
+    fn certificate() -> ParsedCertificate {
+        let (cert, _) = X509CertificateBuilder::default()
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap();
+        ParsedCertificate((*cert).clone())
+    }
+
+    /// Build an unsigned CRL of the issuer of `cert`, which revokes `serial`
+    fn build_crl(cert: &ParsedCertificate, serial: &[u8]) -> CertificateRevocationListDer<'static> {
+        // Ed25519
+        let algorithm = tlv(0x30, &tlv(0x06, &[43, 101, 112]));
+        let issuer = cert.issuer_name().encode_ref().to_captured(Mode::Der);
+        let revoked = tlv(
+            0x30,
+            &tlv(
+                0x30,
+                &[tlv(0x02, serial), tlv(0x17, b"240101000000Z")].concat(),
+            ),
+        );
+        // CRL number
+        let extensions = tlv(
+            0xa0,
+            &tlv(
+                0x30,
+                &tlv(
+                    0x30,
+                    &[tlv(0x06, &[85, 29, 20]), tlv(0x04, &tlv(0x02, &[1]))].concat(),
+                ),
+            ),
+        );
+        let tbs = tlv(
+            0x30,
+            &[
+                tlv(0x02, &[1]),
+                algorithm.clone(),
+                issuer.as_slice().to_vec(),
+                tlv(0x17, b"240101000000Z"),
+                tlv(0x17, b"240108000000Z"),
+                revoked,
+                extensions,
+            ]
+            .concat(),
+        );
+
+        tlv(0x30, &[tbs, algorithm, tlv(0x03, &[0; 65])].concat()).into()
+    }
+
+    #[test]
+    fn parse_crl() {
+        let cert = certificate();
+
+        let crl = CertificateRevocationList::from_der(build_crl(
+            &cert,
+            cert.serial_number_asn1().as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(crl.this_update, 1704067200);
+        assert_eq!(crl.next_update, Some(1704672000));
+        assert_eq!(crl.issuer_common_name(), cert.issuer_common_name());
+        assert_eq!(crl.is_revoked(&cert), Some(true));
+
+        let crl = CertificateRevocationList::from_der(build_crl(&cert, &[0x7f])).unwrap();
+        assert_eq!(crl.is_revoked(&cert), Some(false));
+        // Certificates built for testing have the same issuer, so rename it
+        let mut other = certificate();
+        other
+            .0
+            .as_mut()
+            .tbs_certificate
+            .issuer
+            .append_common_name_utf8_string("Other CA")
+            .unwrap();
+        assert_eq!(crl.is_revoked(&other), None);
+    }
+}
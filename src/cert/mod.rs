@@ -0,0 +1,216 @@
+use anyhow::Result as AnyResult;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use num_bigint::BigUint;
+use rustls_pki_types::ServerName;
+use std::{
+    fmt::{Display, Formatter},
+    ops::Deref,
+};
+use x509_certificate::{
+    asn1time::Time, EcdsaCurve, KeyAlgorithm, SignatureAlgorithm, X509Certificate,
+};
+
+mod crl;
+mod ocsp;
+mod san;
+mod sct;
+
+pub use crl::CertificateRevocationList;
+pub use ocsp::{OcspCertStatus, OcspResponse};
+pub use san::SubjectAltName;
+pub use sct::{CtLogList, CtPolicy, SignedCertificateTimestamp};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedCertificate(pub X509Certificate);
+
+impl ParsedCertificate {
+    pub fn serial_number(&self) -> BigUint {
+        let number = &self.0.as_ref().tbs_certificate.serial_number;
+        BigUint::from_bytes_be(number.as_slice())
+    }
+
+    pub fn not_before(&self) -> i64 {
+        timestamp(&self.0.as_ref().tbs_certificate.validity.not_before)
+    }
+
+    pub fn not_after(&self) -> i64 {
+        timestamp(&self.0.as_ref().tbs_certificate.validity.not_after)
+    }
+
+    pub fn subject_alt_names(&self) -> AnyResult<Vec<SubjectAltName>> {
+        san::subject_alt_names(&self.0)
+    }
+
+    /// The SCTs embedded in the certificate by the CA
+    pub fn embedded_scts(&self) -> AnyResult<Vec<SignedCertificateTimestamp>> {
+        sct::embedded_scts(&self.0)
+    }
+
+    /// Check the names of the certificate only, without verifying the chain.
+    pub fn matches_server_name(&self, server_name: &ServerName<'_>) -> bool {
+        let sans = self.subject_alt_names().unwrap_or_default();
+        san::matches_server_name(server_name, &sans, self.subject_common_name().as_deref())
+    }
+
+    /// The algorithm of the public key, or its OID if it's unknown
+    pub fn key_algorithm_name(&self) -> String {
+        match self.0.key_algorithm() {
+            Some(KeyAlgorithm::Rsa) => "rsa".to_owned(),
+            Some(KeyAlgorithm::Ecdsa(_)) => "ecdsa".to_owned(),
+            Some(KeyAlgorithm::Ed25519) => "ed25519".to_owned(),
+            None => self.0.key_algorithm_oid().to_string(),
+        }
+    }
+
+    /// The size of the public key in bits
+    pub fn key_size(&self) -> Option<u64> {
+        match self.0.key_algorithm()? {
+            KeyAlgorithm::Rsa => {
+                let key = self.0.rsa_public_key_data().ok()?;
+                Some(BigUint::from_bytes_be(key.modulus.as_slice()).bits())
+            }
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1) => Some(256),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1) => Some(384),
+            KeyAlgorithm::Ed25519 => Some(256),
+        }
+    }
+
+    pub fn key_curve(&self) -> Option<&'static str> {
+        match self.0.key_algorithm()? {
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1) => Some("secp256r1"),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1) => Some("secp384r1"),
+            KeyAlgorithm::Ed25519 => Some("ed25519"),
+            KeyAlgorithm::Rsa => None,
+        }
+    }
+
+    /// The algorithm which the issuer signed the certificate with, or its OID if it's unknown
+    pub fn signature_algorithm_name(&self) -> String {
+        match self.0.signature_algorithm() {
+            Some(SignatureAlgorithm::RsaSha1) => "rsa_sha1".to_owned(),
+            Some(SignatureAlgorithm::RsaSha256) => "rsa_sha256".to_owned(),
+            Some(SignatureAlgorithm::RsaSha384) => "rsa_sha384".to_owned(),
+            Some(SignatureAlgorithm::RsaSha512) => "rsa_sha512".to_owned(),
+            Some(SignatureAlgorithm::EcdsaSha256) => "ecdsa_sha256".to_owned(),
+            Some(SignatureAlgorithm::EcdsaSha384) => "ecdsa_sha384".to_owned(),
+            Some(SignatureAlgorithm::Ed25519) => "ed25519".to_owned(),
+            Some(SignatureAlgorithm::NoSignature(_)) | None => {
+                self.0.signature_algorithm_oid().to_string()
+            }
+        }
+    }
+
+    /// The SHA-256 fingerprint in lowercase hex
+    pub fn sha256_fingerprint_hex(&self) -> AnyResult<String> {
+        Ok(self
+            .0
+            .sha256_fingerprint()?
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    pub fn certificate_identifier(&self) -> AnyResult<CertificateIdentifier> {
+        Ok(CertificateIdentifier {
+            serial_number: self.serial_number(),
+            fingerprint: self.0.sha256_fingerprint()?.as_ref().to_owned(),
+        })
+    }
+}
+
+/// The position of a certificate in the chain sent by the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertificateRole {
+    Leaf,
+    Intermediate,
+    /// A self-signed root which the server sent along with the chain
+    Root,
+}
+
+impl CertificateRole {
+    pub fn in_chain(cert: &ParsedCertificate, chain_index: usize) -> Self {
+        if chain_index == 0 {
+            CertificateRole::Leaf
+        } else if cert.subject_is_issuer() {
+            CertificateRole::Root
+        } else {
+            CertificateRole::Intermediate
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificateRole::Leaf => "leaf",
+            CertificateRole::Intermediate => "intermediate",
+            CertificateRole::Root => "root-in-chain",
+        }
+    }
+}
+
+impl Deref for ParsedCertificate {
+    type Target = X509Certificate;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for ParsedCertificate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let der = self.0.encode_der().map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", STANDARD.encode(der))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CertificateIdentifier {
+    serial_number: BigUint,
+    fingerprint: Vec<u8>,
+}
+
+impl Display for CertificateIdentifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}#{}",
+            STANDARD.encode(&self.fingerprint),
+            &self.serial_number
+        )
+    }
+}
+
+fn timestamp(time: &Time) -> i64 {
+    match time {
+        Time::UtcTime(t) => t.timestamp(),
+        Time::GeneralTime(t) => DateTime::<Utc>::from(t.clone()).timestamp(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x509_certificate::X509CertificateBuilder;
+
+    fn certificate(key_algorithm: KeyAlgorithm) -> ParsedCertificate {
+        let (cert, _) = X509CertificateBuilder::default()
+            .create_with_random_keypair(key_algorithm)
+            .unwrap();
+        ParsedCertificate((*cert).clone())
+    }
+
+    #[test]
+    fn key_and_signature_algorithms() {
+        let cert = certificate(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1));
+        assert_eq!(cert.key_algorithm_name(), "ecdsa");
+        assert_eq!(cert.key_size(), Some(256));
+        assert_eq!(cert.key_curve(), Some("secp256r1"));
+        assert_eq!(cert.signature_algorithm_name(), "ecdsa_sha256");
+
+        let cert = certificate(KeyAlgorithm::Ed25519);
+        assert_eq!(cert.key_algorithm_name(), "ed25519");
+        assert_eq!(cert.signature_algorithm_name(), "ed25519");
+        assert_eq!(cert.sha256_fingerprint_hex().unwrap().len(), 64);
+    }
+}
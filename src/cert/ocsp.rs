@@ -0,0 +1,412 @@
+use super::ParsedCertificate;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use bcder::{
+    decode::{Constructed, Content, DecodeError, Source},
+    BitString, Captured, ConstOid, Integer, Mode, OctetString, Oid, Tag,
+};
+use chrono::{DateTime, Utc};
+use x509_certificate::{
+    asn1time::GeneralizedTime, rfc5280::AlgorithmIdentifier, CapturedX509Certificate,
+    SignatureAlgorithm, X509Certificate,
+};
+
+/// 1.3.6.1.5.5.7.48.1.1
+const OID_OCSP_BASIC: ConstOid = Oid(&[43, 6, 1, 5, 5, 7, 48, 1, 1]);
+/// 1.3.6.1.5.5.7.3.9
+const OID_KP_OCSP_SIGNING: ConstOid = Oid(&[43, 6, 1, 5, 5, 7, 3, 9]);
+/// 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: ConstOid = Oid(&[85, 29, 37]);
+const RESPONSE_STATUS_SUCCESSFUL: u8 = 0;
+
+/// The revocation status of a certificate reported by the OCSP responder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OcspCertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+impl OcspCertStatus {
+    pub const ALL: [OcspCertStatus; 3] = [
+        OcspCertStatus::Good,
+        OcspCertStatus::Revoked,
+        OcspCertStatus::Unknown,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OcspCertStatus::Good => "good",
+            OcspCertStatus::Revoked => "revoked",
+            OcspCertStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// The OCSP response stapled by the server, following RFC 6960
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OcspResponse {
+    pub status: OcspCertStatus,
+    pub this_update: i64,
+    pub next_update: Option<i64>,
+    /// Whether the response is signed by the issuer, or by a responder which the issuer
+    /// delegated. `None` if the server didn't send the issuer.
+    pub signature_valid: Option<bool>,
+}
+
+impl OcspResponse {
+    /// Parse the status of `cert` from a DER encoded `OCSPResponse`.
+    pub fn parse(
+        der: &[u8],
+        cert: &ParsedCertificate,
+        issuer: Option<&ParsedCertificate>,
+    ) -> AnyResult<Self> {
+        let basic = BasicResponse::decode(der)?;
+        let single = basic
+            .responses
+            .iter()
+            .find(|single| single.serial_number.as_slice() == cert.serial_number_asn1().as_slice())
+            .ok_or_else(|| {
+                ErrorReason::OcspResponse("the certificate is not in the response".to_owned())
+            })?;
+
+        Ok(Self {
+            status: single.status,
+            this_update: single.this_update,
+            next_update: single.next_update,
+            signature_valid: issuer.map(|issuer| basic.verify_signature(issuer)),
+        })
+    }
+}
+
+/// ```ASN.1
+/// BasicOCSPResponse ::= SEQUENCE {
+///     tbsResponseData      ResponseData,
+///     signatureAlgorithm   AlgorithmIdentifier,
+///     signature            BIT STRING,
+///     certs            [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL }
+/// ```
+struct BasicResponse {
+    tbs_response_data: Captured,
+    signature_algorithm: AlgorithmIdentifier,
+    signature: BitString,
+    certs: Vec<Captured>,
+    responses: Vec<SingleResponse>,
+}
+
+impl BasicResponse {
+    /// Decode the `OCSPResponse` wrapping the basic response.
+    fn decode(der: &[u8]) -> AnyResult<Self> {
+        let (status, response) = Mode::Der.decode(der, |cons| {
+            cons.take_sequence(|cons| {
+                let status = cons.take_primitive_if(Tag::ENUMERATED, |prim| prim.to_u8())?;
+                let response = cons.take_opt_constructed_if(Tag::CTX_0, |cons| {
+                    cons.take_sequence(|cons| {
+                        let response_type = Oid::take_from(cons)?;
+                        let response = OctetString::take_from(cons)?;
+                        Ok((response_type, response))
+                    })
+                })?;
+                Ok((status, response))
+            })
+        })?;
+
+        if status != RESPONSE_STATUS_SUCCESSFUL {
+            return Err(
+                ErrorReason::OcspResponse(format!("unsuccessful status {}", status)).into(),
+            );
+        }
+        let response = match response {
+            Some((response_type, response)) if response_type == OID_OCSP_BASIC => response,
+            _ => {
+                return Err(
+                    ErrorReason::OcspResponse("not a basic OCSP response".to_owned()).into(),
+                )
+            }
+        };
+
+        let mut basic = Mode::Der.decode(response.to_bytes(), |cons| {
+            cons.take_sequence(|cons| {
+                let tbs_response_data = cons.capture_one()?;
+                let signature_algorithm = AlgorithmIdentifier::take_from(cons)?;
+                let signature = BitString::take_from(cons)?;
+                let certs = cons
+                    .take_opt_constructed_if(Tag::CTX_0, |cons| {
+                        cons.take_sequence(|cons| {
+                            let mut certs = Vec::new();
+                            loop {
+                                let cert = cons.capture(|cons| cons.skip_one().map(|_| ()))?;
+                                if cert.as_slice().is_empty() {
+                                    break;
+                                }
+                                certs.push(cert);
+                            }
+                            Ok(certs)
+                        })
+                    })?
+                    .unwrap_or_default();
+                Ok(Self {
+                    tbs_response_data,
+                    signature_algorithm,
+                    signature,
+                    certs,
+                    responses: Vec::new(),
+                })
+            })
+        })?;
+
+        basic.responses = Mode::Der.decode(basic.tbs_response_data.as_slice(), |cons| {
+            cons.take_sequence(|cons| {
+                // version [0] EXPLICIT Version DEFAULT v1
+                cons.take_opt_constructed_if(Tag::CTX_0, |cons| cons.skip_all())?;
+                // responderID
+                cons.take_value(|_, content| skip_content(content))?;
+                // producedAt
+                GeneralizedTime::take_from_allow_fractional_z(cons)?;
+                let responses = cons.take_sequence(|cons| {
+                    let mut responses = Vec::new();
+                    while let Some(single) =
+                        cons.take_opt_sequence(SingleResponse::from_sequence)?
+                    {
+                        responses.push(single);
+                    }
+                    Ok(responses)
+                })?;
+                // responseExtensions
+                cons.skip_all()?;
+                Ok(responses)
+            })
+        })?;
+
+        Ok(basic)
+    }
+
+    /// Check the signature with the key of the issuer, or of an embedded responder certificate
+    /// issued by it for OCSP signing.
+    fn verify_signature(&self, issuer: &ParsedCertificate) -> bool {
+        let Ok(issuer) = CapturedX509Certificate::try_from(&issuer.0) else {
+            return false;
+        };
+        if self.signed_by(&issuer) {
+            return true;
+        }
+
+        self.certs.iter().any(|cert| {
+            CapturedX509Certificate::from_der(cert.as_slice()).map_or(false, |responder| {
+                responder.verify_signed_by_certificate(&issuer).is_ok()
+                    && is_ocsp_signer(&responder)
+                    && self.signed_by(&responder)
+            })
+        })
+    }
+
+    fn signed_by(&self, signer: &CapturedX509Certificate) -> bool {
+        let Ok(signature_algorithm) = SignatureAlgorithm::try_from(&self.signature_algorithm)
+        else {
+            return false;
+        };
+        let Some(key_algorithm) = signer.key_algorithm() else {
+            return false;
+        };
+        let Ok(verify_algorithm) =
+            signature_algorithm.resolve_verification_algorithm(key_algorithm)
+        else {
+            return false;
+        };
+
+        signer
+            .verify_signed_data_with_algorithm(
+                self.tbs_response_data.as_slice(),
+                self.signature.octet_bytes(),
+                verify_algorithm,
+            )
+            .is_ok()
+    }
+}
+
+/// ```ASN.1
+/// SingleResponse ::= SEQUENCE {
+///     certID                       CertID,
+///     certStatus                   CertStatus,
+///     thisUpdate                   GeneralizedTime,
+///     nextUpdate         [0]       EXPLICIT GeneralizedTime OPTIONAL,
+///     singleExtensions   [1]       EXPLICIT Extensions OPTIONAL }
+/// ```
+struct SingleResponse {
+    serial_number: Integer,
+    status: OcspCertStatus,
+    this_update: i64,
+    next_update: Option<i64>,
+}
+
+impl SingleResponse {
+    fn from_sequence<S: Source>(cons: &mut Constructed<S>) -> Result<Self, DecodeError<S::Error>> {
+        let serial_number = cons.take_sequence(|cons| {
+            AlgorithmIdentifier::take_from(cons)?;
+            // issuerNameHash and issuerKeyHash
+            OctetString::take_from(cons)?;
+            OctetString::take_from(cons)?;
+            Integer::take_from(cons)
+        })?;
+        let status = cons.take_value(|tag, content| {
+            let status = if tag == Tag::CTX_0 {
+                OcspCertStatus::Good
+            } else if tag == Tag::CTX_1 {
+                OcspCertStatus::Revoked
+            } else if tag == Tag::CTX_2 {
+                OcspCertStatus::Unknown
+            } else {
+                return Err(content.content_err("invalid certificate status"));
+            };
+            skip_content(content)?;
+            Ok(status)
+        })?;
+        let this_update = GeneralizedTime::take_from_allow_fractional_z(cons)?;
+        let next_update = cons.take_opt_constructed_if(Tag::CTX_0, |cons| {
+            GeneralizedTime::take_from_allow_fractional_z(cons)
+        })?;
+        cons.skip_all()?;
+
+        Ok(Self {
+            serial_number,
+            status,
+            this_update: DateTime::<Utc>::from(this_update).timestamp(),
+            next_update: next_update.map(|time| DateTime::<Utc>::from(time).timestamp()),
+        })
+    }
+}
+
+fn skip_content<S: Source>(content: &mut Content<S>) -> Result<(), DecodeError<S::Error>> {
+    match content {
+        Content::Primitive(primitive) => primitive.skip_all(),
+        Content::Constructed(constructed) => constructed.skip_all(),
+    }
+}
+
+/// Whether the extended key usage allows signing OCSP responses
+fn is_ocsp_signer(cert: &X509Certificate) -> bool {
+    cert.iter_extensions()
+        .filter(|extension| extension.id == OID_EXTENDED_KEY_USAGE)
+        .any(|extension| {
+            Mode::Der
+                .decode(extension.value.to_bytes(), |cons| {
+                    cons.take_sequence(|cons| {
+                        let mut found = false;
+                        while let Some(purpose) = Oid::take_opt_from(cons)? {
+                            found |= purpose == OID_KP_OCSP_SIGNING;
+                        }
+                        Ok(found)
+                    })
+                })
+                .unwrap_or(false)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x509_certificate::{KeyAlgorithm, KeyInfoSigner, X509CertificateBuilder};
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => der.push(len as u8),
+            len @ 0x80..=0xff => der.extend([0x81, len as u8]),
+            len => der.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        der.extend(content);
+        der
+    }
+
+    fn certificate() -> (
+        CapturedX509Certificate,
+        x509_certificate::InMemorySigningKeyPair,
+    ) {
+        X509CertificateBuilder::default()
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap()
+    }
+
+    /// Build a response with a single good status for `cert`, signed by `key`
+    fn response(cert: &X509Certificate, key: &impl KeyInfoSigner) -> Vec<u8> {
+        let cert_id = [
+            tlv(0x30, &tlv(0x06, &[43, 14, 3, 2, 26])),
+            tlv(0x04, &[0; 20]),
+            tlv(0x04, &[0; 20]),
+            tlv(0x02, cert.serial_number_asn1().as_slice()),
+        ]
+        .concat();
+        let single = [
+            tlv(0x30, &cert_id),
+            tlv(0x80, &[]),
+            tlv(0x18, b"20240101000000Z"),
+            tlv(0xa0, &tlv(0x18, b"20240108000000Z")),
+        ]
+        .concat();
+        let tbs = tlv(
+            0x30,
+            &[
+                tlv(0xa2, &tlv(0x04, &[0; 20])),
+                tlv(0x18, b"20240101000000Z"),
+                tlv(0x30, &tlv(0x30, &single)),
+            ]
+            .concat(),
+        );
+
+        let signature = key.try_sign(&tbs).unwrap();
+        let algorithm = key.signature_algorithm().unwrap();
+        let basic = tlv(
+            0x30,
+            &[
+                tbs,
+                tlv(0x30, &tlv(0x06, Oid::from(algorithm).as_ref())),
+                tlv(0x03, &[&[0], signature.as_ref()].concat()),
+            ]
+            .concat(),
+        );
+
+        tlv(
+            0x30,
+            &[
+                tlv(0x0a, &[RESPONSE_STATUS_SUCCESSFUL]),
+                tlv(
+                    0xa0,
+                    &tlv(
+                        0x30,
+                        &[tlv(0x06, OID_OCSP_BASIC.as_ref()), tlv(0x04, &basic)].concat(),
+                    ),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn parse_response() {
+        let (leaf, _) = certificate();
+        let (issuer, issuer_key) = certificate();
+        let (other, _) = certificate();
+        let leaf = ParsedCertificate((*leaf).clone());
+        let issuer = ParsedCertificate((*issuer).clone());
+        let other = ParsedCertificate((*other).clone());
+        let der = response(&leaf, &issuer_key);
+
+        assert_eq!(
+            OcspResponse::parse(&der, &leaf, Some(&issuer)).unwrap(),
+            OcspResponse {
+                status: OcspCertStatus::Good,
+                this_update: 1704067200,
+                next_update: Some(1704672000),
+                signature_valid: Some(true),
+            }
+        );
+        let response = OcspResponse::parse(&der, &leaf, Some(&other)).unwrap();
+        assert_eq!(response.signature_valid, Some(false));
+        let response = OcspResponse::parse(&der, &leaf, None).unwrap();
+        assert_eq!(response.signature_valid, None);
+
+        // tryLater
+        let der = tlv(0x30, &tlv(0x0a, &[3]));
+        assert!(OcspResponse::parse(&der, &leaf, Some(&issuer)).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::Result as AnyResult;
+use bcder::{decode::Content, ConstOid, Mode, Oid, Tag};
+use rustls_pki_types::ServerName;
+use std::{
+    fmt::{Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+use x509_certificate::X509Certificate;
+
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: ConstOid = Oid(&[85, 29, 17]);
+
+/// The supported types of the Subject Alternative Name extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Uri(String),
+    Email(String),
+}
+
+impl SubjectAltName {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SubjectAltName::Dns(_) => "dns",
+            SubjectAltName::Ip(_) => "ip",
+            SubjectAltName::Uri(_) => "uri",
+            SubjectAltName::Email(_) => "email",
+        }
+    }
+}
+
+impl Display for SubjectAltName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) => write!(f, "{}", name),
+            SubjectAltName::Ip(addr) => write!(f, "{}", addr),
+            SubjectAltName::Uri(uri) => write!(f, "{}", uri),
+            SubjectAltName::Email(email) => write!(f, "{}", email),
+        }
+    }
+}
+
+/// Parse the Subject Alternative Names of the certificate. Unsupported types are ignored.
+pub fn subject_alt_names(cert: &X509Certificate) -> AnyResult<Vec<SubjectAltName>> {
+    let mut names = Vec::new();
+    for extension in cert.iter_extensions() {
+        if extension.id == OID_SUBJECT_ALT_NAME {
+            names.extend(parse_general_names(&extension.value.to_bytes())?);
+        }
+    }
+    Ok(names)
+}
+
+/// ```ASN.1
+/// GeneralNames ::= SEQUENCE SIZE (1..MAX) OF GeneralName
+/// ```
+///
+/// The names are implicitly tagged, so the supported ones are all primitive values.
+fn parse_general_names(der: &[u8]) -> AnyResult<Vec<SubjectAltName>> {
+    let names = Mode::Der.decode(der, |cons| {
+        cons.take_sequence(|cons| {
+            let mut names = Vec::new();
+            while let Some(name) = cons.take_opt_value(|tag, content| {
+                let bytes = match content {
+                    Content::Primitive(primitive) => primitive.take_all()?,
+                    Content::Constructed(constructed) => {
+                        constructed.skip_all()?;
+                        return Ok(None);
+                    }
+                };
+                let text = || String::from_utf8_lossy(&bytes).into_owned();
+                let name = if tag == Tag::CTX_1 {
+                    Some(SubjectAltName::Email(text()))
+                } else if tag == Tag::CTX_2 {
+                    Some(SubjectAltName::Dns(text()))
+                } else if tag == Tag::CTX_6 {
+                    Some(SubjectAltName::Uri(text()))
+                } else if tag == Tag::ctx(7) {
+                    parse_ip_address(&bytes).map(SubjectAltName::Ip)
+                } else {
+                    None
+                };
+                Ok(name)
+            })? {
+                names.extend(name);
+            }
+            Ok(names)
+        })
+    })?;
+    Ok(names)
+}
+
+fn parse_ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(bytes) {
+        Some(Ipv4Addr::from(octets).into())
+    } else if let Ok(octets) = <[u8; 16]>::try_from(bytes) {
+        Some(Ipv6Addr::from(octets).into())
+    } else {
+        None
+    }
+}
+
+/// Check whether the certificate is valid for the server name, following RFC 6125.
+///
+/// The common name is only checked when there are no DNS names, and wildcards only match a
+/// whole leftmost label.
+pub fn matches_server_name(
+    server_name: &ServerName<'_>,
+    sans: &[SubjectAltName],
+    common_name: Option<&str>,
+) -> bool {
+    match server_name {
+        ServerName::DnsName(name) => {
+            let mut dns_names = sans
+                .iter()
+                .filter_map(|san| match san {
+                    SubjectAltName::Dns(name) => Some(name.as_str()),
+                    _ => None,
+                })
+                .peekable();
+
+            if dns_names.peek().is_some() {
+                dns_names
+                    .into_iter()
+                    .any(|pattern| matches_dns_name(pattern, name.as_ref()))
+            } else {
+                common_name.map_or(false, |pattern| matches_dns_name(pattern, name.as_ref()))
+            }
+        }
+        ServerName::IpAddress(addr) => {
+            let addr = IpAddr::from(*addr);
+            sans.iter()
+                .any(|san| matches!(san, SubjectAltName::Ip(ip) if *ip == addr))
+        }
+        _ => false,
+    }
+}
+
+fn matches_dns_name(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        // The wildcard must not cover a public suffix like `*.com`
+        Some(parent) if parent.contains('.') => name
+            .split_once('.')
+            .map_or(false, |(label, rest)| !label.is_empty() && rest == parent),
+        Some(_) => false,
+        None => pattern == name,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dns(name: &'static str) -> ServerName<'static> {
+        ServerName::try_from(name).unwrap()
+    }
+
+    #[test]
+    fn parse_names() {
+        // SEQUENCE { [2] "example.com", [7] 192.0.2.1, [1] "a@example.com", [6] "https://example.com/" }
+        let mut der = vec![0x30, 0x00];
+        der.extend([0x82, 11]);
+        der.extend(b"example.com");
+        der.extend([0x87, 4, 192, 0, 2, 1]);
+        der.extend([0x81, 13]);
+        der.extend(b"a@example.com");
+        der.extend([0x86, 20]);
+        der.extend(b"https://example.com/");
+        der[1] = (der.len() - 2) as u8;
+
+        assert_eq!(
+            parse_general_names(&der).unwrap(),
+            [
+                SubjectAltName::Dns("example.com".to_owned()),
+                SubjectAltName::Ip("192.0.2.1".parse().unwrap()),
+                SubjectAltName::Email("a@example.com".to_owned()),
+                SubjectAltName::Uri("https://example.com/".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(matches_dns_name("*.example.com", "www.example.com"));
+        assert!(matches_dns_name("*.Example.com", "WWW.example.com."));
+        assert!(!matches_dns_name("*.example.com", "example.com"));
+        assert!(!matches_dns_name("*.example.com", "a.b.example.com"));
+        assert!(!matches_dns_name("*.com", "example.com"));
+        assert!(!matches_dns_name("w*.example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn match_server_names() {
+        let sans = [
+            SubjectAltName::Dns("example.com".to_owned()),
+            SubjectAltName::Ip("192.0.2.1".parse().unwrap()),
+        ];
+
+        assert!(matches_server_name(&dns("example.com"), &sans, None));
+        assert!(matches_server_name(&dns("192.0.2.1"), &sans, None));
+        assert!(!matches_server_name(&dns("192.0.2.2"), &sans, None));
+        // The common name is ignored when there are DNS names
+        assert!(!matches_server_name(
+            &dns("example.net"),
+            &sans,
+            Some("example.net")
+        ));
+        assert!(matches_server_name(
+            &dns("example.net"),
+            &[],
+            Some("example.net")
+        ));
+    }
+}
@@ -0,0 +1,469 @@
+use super::ParsedCertificate;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bcder::{encode::Values, ConstOid, Mode, OctetString, Oid, Tag};
+use chrono::DateTime;
+use rustls_pki_types::SignatureVerificationAlgorithm;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use x509_certificate::{
+    rfc5280::SubjectPublicKeyInfo, DigestAlgorithm, EcdsaCurve, KeyAlgorithm, X509Certificate,
+};
+
+/// 1.3.6.1.4.1.11129.2.4.2
+const OID_SCT_LIST: ConstOid = Oid(&[43, 6, 1, 4, 1, 214, 121, 2, 4, 2]);
+const SCT_VERSION_V1: u8 = 0;
+const SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP: u8 = 0;
+const LOG_ENTRY_TYPE_PRECERT: u16 = 1;
+const HASH_ALGORITHM_SHA256: u8 = 4;
+const SIGNATURE_ALGORITHM_RSA: u8 = 1;
+const SIGNATURE_ALGORITHM_ECDSA: u8 = 3;
+
+/// A Signed Certificate Timestamp embedded in a certificate, see RFC 6962
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedCertificateTimestamp {
+    pub log_id: [u8; 32],
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    extensions: Vec<u8>,
+    hash_algorithm: u8,
+    signature_algorithm: u8,
+    signature: Vec<u8>,
+}
+
+impl SignedCertificateTimestamp {
+    pub fn log_id_base64(&self) -> String {
+        STANDARD.encode(self.log_id)
+    }
+
+    /// The data signed by the log for a precertificate entry
+    fn signed_data(&self, issuer_key_hash: &[u8], tbs_certificate: &[u8]) -> Vec<u8> {
+        let mut data = vec![SCT_VERSION_V1, SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP];
+        data.extend(self.timestamp.to_be_bytes());
+        data.extend(LOG_ENTRY_TYPE_PRECERT.to_be_bytes());
+        data.extend(issuer_key_hash);
+        data.extend(&(tbs_certificate.len() as u32).to_be_bytes()[1..]);
+        data.extend(tbs_certificate);
+        data.extend((self.extensions.len() as u16).to_be_bytes());
+        data.extend(&self.extensions);
+        data
+    }
+}
+
+/// Parse the SCT list extension of the certificate.
+pub fn embedded_scts(cert: &X509Certificate) -> AnyResult<Vec<SignedCertificateTimestamp>> {
+    let mut scts = Vec::new();
+    for extension in cert.iter_extensions() {
+        if extension.id == OID_SCT_LIST {
+            // The TLS encoded list is wrapped in another OCTET STRING
+            let list = Mode::Der.decode(extension.value.to_bytes(), OctetString::take_from)?;
+            scts.extend(parse_sct_list(&list.to_bytes()).ok_or_else(invalid_sct_list)?);
+        }
+    }
+    Ok(scts)
+}
+
+fn invalid_sct_list() -> ErrorReason {
+    ErrorReason::InvalidSct("malformed SCT list".to_owned())
+}
+
+/// ```text
+/// opaque SerializedSCT<1..2^16-1>;
+/// struct {
+///     SerializedSCT sct_list <1..2^16-1>;
+/// } SignedCertificateTimestampList;
+/// ```
+fn parse_sct_list(data: &[u8]) -> Option<Vec<SignedCertificateTimestamp>> {
+    let mut reader = Reader(data);
+    let mut list = Reader(reader.take_u16_prefixed()?);
+    let mut scts = Vec::new();
+    while !list.0.is_empty() {
+        let mut sct = Reader(list.take_u16_prefixed()?);
+        // Unknown versions can't be parsed, and are ignored by the clients as well
+        if sct.take(1)? != [SCT_VERSION_V1] {
+            continue;
+        }
+        scts.push(SignedCertificateTimestamp {
+            log_id: sct.take(32)?.try_into().ok()?,
+            timestamp: u64::from_be_bytes(sct.take(8)?.try_into().ok()?),
+            extensions: sct.take_u16_prefixed()?.to_vec(),
+            hash_algorithm: sct.take(1)?[0],
+            signature_algorithm: sct.take(1)?[0],
+            signature: sct.take_u16_prefixed()?.to_vec(),
+        });
+    }
+    Some(scts)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn take_u16_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().ok()?);
+        self.take(len as usize)
+    }
+}
+
+/// The Certificate Transparency logs trusted for the policy, loaded from a log list in the
+/// v3 JSON format published by Google
+#[derive(Debug, Default)]
+pub struct CtLogList {
+    /// The logs which count toward the policy by their ID
+    logs: HashMap<[u8; 32], CtLog>,
+}
+
+#[derive(Debug)]
+struct CtLog {
+    key: SubjectPublicKeyInfo,
+    /// For a retired log, only the SCTs issued before this time in milliseconds are accepted
+    retired_at: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct LogListJson {
+    operators: Vec<OperatorJson>,
+}
+
+#[derive(Deserialize)]
+struct OperatorJson {
+    logs: Vec<LogJson>,
+}
+
+#[derive(Deserialize)]
+struct LogJson {
+    log_id: String,
+    key: String,
+    /// A single state, e.g. `{"usable": {"timestamp": "2023-01-01T00:00:00Z"}}`
+    #[serde(default)]
+    state: HashMap<String, LogStateJson>,
+}
+
+#[derive(Deserialize)]
+struct LogStateJson {
+    timestamp: String,
+}
+
+impl CtLogList {
+    pub fn from_json(data: &[u8]) -> AnyResult<Self> {
+        let list: LogListJson = serde_json::from_slice(data)?;
+        let mut logs = HashMap::new();
+        for log in list
+            .operators
+            .into_iter()
+            .flat_map(|operator| operator.logs)
+        {
+            // Pending and rejected logs, or the ones without a state, are not trusted
+            let retired_at = if let Some(state) = log.state.get("retired") {
+                let retired_at = DateTime::parse_from_rfc3339(&state.timestamp).map_err(|_| {
+                    ErrorReason::InvalidSct(format!("invalid timestamp {}", state.timestamp))
+                })?;
+                Some(retired_at.timestamp_millis() as u64)
+            } else if ["qualified", "usable", "readonly"]
+                .iter()
+                .any(|state| log.state.contains_key(*state))
+            {
+                None
+            } else {
+                continue;
+            };
+
+            let log_id = STANDARD
+                .decode(&log.log_id)?
+                .try_into()
+                .map_err(|_| ErrorReason::InvalidSct(format!("invalid log ID {}", log.log_id)))?;
+            let key = STANDARD.decode(&log.key)?;
+            let key = Mode::Der.decode(key.as_slice(), SubjectPublicKeyInfo::take_from)?;
+            logs.insert(log_id, CtLog { key, retired_at });
+        }
+        Ok(Self { logs })
+    }
+
+    /// Check the signature of an SCT embedded in `cert`, which is issued by `issuer`.
+    pub fn verify(
+        &self,
+        sct: &SignedCertificateTimestamp,
+        cert: &ParsedCertificate,
+        issuer: &ParsedCertificate,
+    ) -> bool {
+        let Some(log) = self.logs.get(&sct.log_id) else {
+            return false;
+        };
+        if matches!(log.retired_at, Some(retired_at) if sct.timestamp >= retired_at) {
+            return false;
+        }
+        let key = &log.key;
+        let Some(algorithm) = verification_algorithm(sct, key) else {
+            return false;
+        };
+        let Some(tbs_certificate) = precertificate_tbs(cert) else {
+            return false;
+        };
+        let issuer_key = issuer
+            .as_ref()
+            .tbs_certificate
+            .subject_public_key_info
+            .encode_ref()
+            .to_captured(Mode::Der);
+        let issuer_key_hash = DigestAlgorithm::Sha256.digest_data(issuer_key.as_slice());
+
+        algorithm
+            .verify_signature(
+                key.subject_public_key.octet_bytes().as_ref(),
+                &sct.signed_data(&issuer_key_hash, &tbs_certificate),
+                &sct.signature,
+            )
+            .is_ok()
+    }
+}
+
+fn verification_algorithm(
+    sct: &SignedCertificateTimestamp,
+    key: &SubjectPublicKeyInfo,
+) -> Option<&'static dyn SignatureVerificationAlgorithm> {
+    if sct.hash_algorithm != HASH_ALGORITHM_SHA256 {
+        return None;
+    }
+    match (
+        sct.signature_algorithm,
+        KeyAlgorithm::try_from(&key.algorithm).ok()?,
+    ) {
+        (SIGNATURE_ALGORITHM_ECDSA, KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1)) => {
+            Some(webpki::ring::ECDSA_P256_SHA256)
+        }
+        (SIGNATURE_ALGORITHM_ECDSA, KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1)) => {
+            Some(webpki::ring::ECDSA_P384_SHA256)
+        }
+        (SIGNATURE_ALGORITHM_RSA, KeyAlgorithm::Rsa) => {
+            Some(webpki::ring::RSA_PKCS1_2048_8192_SHA256)
+        }
+        _ => None,
+    }
+}
+
+/// Rebuild the TBSCertificate of the precertificate, by removing the SCT list extension from
+/// the original DER. Re-encoding the parsed certificate may not give the same bytes.
+fn precertificate_tbs(cert: &ParsedCertificate) -> Option<Vec<u8>> {
+    let raw_tbs = cert.as_ref().tbs_certificate.raw_data.as_ref()?;
+    Mode::Der
+        .decode(raw_tbs.as_slice(), |cons| {
+            cons.take_sequence(|cons| {
+                let mut fields = Vec::new();
+                loop {
+                    let extensions = cons.take_opt_constructed_if(Tag::CTX_3, |cons| {
+                        cons.take_sequence(|cons| {
+                            let mut extensions = Vec::new();
+                            loop {
+                                let extension = cons.capture(|cons| cons.skip_one().map(|_| ()))?;
+                                if extension.as_slice().is_empty() {
+                                    break;
+                                }
+                                let id = Mode::Der.decode(extension.as_slice(), |cons| {
+                                    cons.take_sequence(|cons| {
+                                        let id = Oid::take_from(cons)?;
+                                        cons.skip_all()?;
+                                        Ok(id)
+                                    })
+                                });
+                                if !matches!(id, Ok(id) if id == OID_SCT_LIST) {
+                                    extensions.extend(extension.as_slice());
+                                }
+                            }
+                            Ok(extensions)
+                        })
+                    })?;
+                    if let Some(extensions) = extensions {
+                        if !extensions.is_empty() {
+                            fields.extend(encode_tlv(0xa3, &encode_tlv(0x30, &extensions)));
+                        }
+                        continue;
+                    }
+
+                    let field = cons.capture(|cons| cons.skip_one().map(|_| ()))?;
+                    if field.as_slice().is_empty() {
+                        break;
+                    }
+                    fields.extend(field.as_slice());
+                }
+                Ok(encode_tlv(0x30, &fields))
+            })
+        })
+        .ok()
+}
+
+/// Encode a DER value with a single-byte tag
+fn encode_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    if content.len() < 0x80 {
+        der.push(content.len() as u8);
+    } else {
+        let len = (content.len() as u32).to_be_bytes();
+        let skip = len.iter().take_while(|byte| **byte == 0).count();
+        der.push(0x80 | (len.len() - skip) as u8);
+        der.extend(&len[skip..]);
+    }
+    der.extend(content);
+    der
+}
+
+/// Require valid SCTs from a number of distinct logs, like the CT policies of the browsers
+#[derive(Debug)]
+pub struct CtPolicy {
+    pub log_list: CtLogList,
+    pub min_logs: usize,
+}
+
+impl CtPolicy {
+    /// Whether the leaf certificate complies with the policy. The issuer is needed to verify
+    /// the embedded SCTs.
+    pub fn is_compliant(
+        &self,
+        leaf: &ParsedCertificate,
+        issuer: Option<&ParsedCertificate>,
+    ) -> bool {
+        let Some(issuer) = issuer else {
+            return false;
+        };
+        let scts = leaf.embedded_scts().unwrap_or_default();
+        let logs: HashSet<[u8; 32]> = scts
+            .iter()
+            .filter(|sct| self.log_list.verify(sct, leaf, issuer))
+            .map(|sct| sct.log_id)
+            .collect();
+        logs.len() >= self.min_logs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x509_certificate::{Signer, X509CertificateBuilder};
+
+    fn u16_prefixed(data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u16).to_be_bytes()[..], data].concat()
+    }
+
+    #[test]
+    fn verify_embedded_sct() {
+        let (issuer, _) = X509CertificateBuilder::default()
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap();
+        let issuer = ParsedCertificate((*issuer).clone());
+        let mut builder = X509CertificateBuilder::default();
+        builder.constraint_not_ca();
+        let (leaf, _) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap();
+        let leaf = ParsedCertificate((*leaf).clone());
+        let (log, log_key) = X509CertificateBuilder::default()
+            .create_with_random_keypair(KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1))
+            .unwrap();
+        let log = ParsedCertificate((*log).clone());
+        let log_spki = log
+            .as_ref()
+            .tbs_certificate
+            .subject_public_key_info
+            .encode_ref()
+            .to_captured(Mode::Der);
+        let log_id: [u8; 32] = DigestAlgorithm::Sha256
+            .digest_data(log_spki.as_slice())
+            .try_into()
+            .unwrap();
+
+        // Sign the TBSCertificate without the SCT list, like a log signs the precertificate
+        let mut sct = SignedCertificateTimestamp {
+            log_id,
+            timestamp: 1704067200123,
+            extensions: Vec::new(),
+            hash_algorithm: HASH_ALGORITHM_SHA256,
+            signature_algorithm: SIGNATURE_ALGORITHM_ECDSA,
+            signature: Vec::new(),
+        };
+        let issuer_key = issuer
+            .as_ref()
+            .tbs_certificate
+            .subject_public_key_info
+            .encode_ref()
+            .to_captured(Mode::Der);
+        let signed_data = sct.signed_data(
+            &DigestAlgorithm::Sha256.digest_data(issuer_key.as_slice()),
+            leaf.as_ref().tbs_certificate.raw_data.as_ref().unwrap(),
+        );
+        let signature = log_key.try_sign(&signed_data).unwrap();
+        sct.signature = signature.as_ref().to_vec();
+
+        let serialized = [
+            &[SCT_VERSION_V1][..],
+            &sct.log_id,
+            &sct.timestamp.to_be_bytes(),
+            &u16_prefixed(&sct.extensions),
+            &[sct.hash_algorithm, sct.signature_algorithm],
+            &u16_prefixed(&sct.signature),
+        ]
+        .concat();
+        let list = encode_tlv(0x04, &u16_prefixed(&u16_prefixed(&serialized)));
+        let mut cert = leaf.0.clone();
+        cert.as_mut()
+            .tbs_certificate
+            .extensions
+            .get_or_insert_with(Default::default)
+            .push(x509_certificate::rfc5280::Extension {
+                id: Oid(OID_SCT_LIST.0.into()),
+                critical: Some(false),
+                value: OctetString::new(list.into()),
+            });
+        let cert =
+            ParsedCertificate(X509Certificate::from_der(cert.encode_der().unwrap()).unwrap());
+
+        assert_eq!(cert.embedded_scts().unwrap(), vec![sct.clone()]);
+        assert_eq!(sct.timestamp / 1000, 1704067200);
+
+        let log_list_with_state = |state: &str| {
+            CtLogList::from_json(
+                format!(
+                    r#"{{"operators": [{{"name": "Test", "logs": [{{"description": "Test log", "log_id": "{}", "key": "{}", "state": {{"{}": {{"timestamp": "2024-01-01T00:00:00Z"}}}}}}]}}]}}"#,
+                    sct.log_id_base64(),
+                    STANDARD.encode(log_spki.as_slice()),
+                    state,
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+        };
+        // The SCT was issued just after the log was retired
+        assert!(!log_list_with_state("retired").verify(&sct, &cert, &issuer));
+        assert!(!log_list_with_state("pending").verify(&sct, &cert, &issuer));
+        assert!(!log_list_with_state("rejected").verify(&sct, &cert, &issuer));
+        assert!(log_list_with_state("readonly").verify(&sct, &cert, &issuer));
+
+        let log_list = log_list_with_state("usable");
+        assert!(log_list.verify(&sct, &cert, &issuer));
+        // The SCT is only valid for the issuer it was signed with
+        let (other, _) = X509CertificateBuilder::default()
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap();
+        assert!(!log_list.verify(&sct, &cert, &ParsedCertificate((*other).clone())));
+
+        let policy = CtPolicy {
+            log_list,
+            min_logs: 1,
+        };
+        assert!(policy.is_compliant(&cert, Some(&issuer)));
+        assert!(!policy.is_compliant(&cert, None));
+        assert!(!policy.is_compliant(&leaf, Some(&issuer)));
+        let policy = CtPolicy {
+            min_logs: 2,
+            ..policy
+        };
+        assert!(!policy.is_compliant(&cert, Some(&issuer)));
+    }
+}
@@ -1,4 +1,5 @@
-use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use crate::error::VerifyReason;
+use rustls_pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
 use std::{fmt::Debug, sync::Arc};
 use tokio::sync::OnceCell;
 use tokio_rustls::rustls::{
@@ -14,14 +15,25 @@ use tokio_rustls::rustls::{
 #[derive(Clone, Debug)]
 pub struct CertificateInterceptor {
     certificates: OnceCell<Vec<CertificateDer<'static>>>,
+    ocsp_response: OnceCell<Vec<u8>>,
+    verification: OnceCell<Result<(), VerifyReason>>,
     verifier: Arc<WebPkiServerVerifier>,
     insecure_skip_verify: bool,
 }
 
 impl CertificateInterceptor {
-    pub fn new(roots: Arc<RootCertStore>, insecure_skip_verify: bool) -> Self {
+    /// Certificates which are not covered by any of the CRLs are not considered revoked.
+    pub fn new(
+        roots: Arc<RootCertStore>,
+        crls: impl IntoIterator<Item = CertificateRevocationListDer<'static>>,
+        insecure_skip_verify: bool,
+    ) -> Self {
         Self::with_verifier(
-            WebPkiServerVerifier::builder(roots).build().unwrap(),
+            WebPkiServerVerifier::builder(roots)
+                .with_crls(crls)
+                .allow_unknown_revocation_status()
+                .build()
+                .unwrap(),
             insecure_skip_verify,
         )
     }
@@ -29,6 +41,8 @@ impl CertificateInterceptor {
     pub fn with_verifier(verifier: Arc<WebPkiServerVerifier>, insecure_skip_verify: bool) -> Self {
         Self {
             certificates: Default::default(),
+            ocsp_response: Default::default(),
+            verification: Default::default(),
             verifier,
             insecure_skip_verify,
         }
@@ -39,12 +53,22 @@ impl CertificateInterceptor {
         root_certs
             .roots
             .extend_from_slice(webpki_roots::TLS_SERVER_ROOTS);
-        Self::new(Arc::new(root_certs), false)
+        Self::new(Arc::new(root_certs), Vec::new(), false)
     }
 
     pub fn get_certificates(&mut self) -> Option<Vec<CertificateDer<'static>>> {
         self.certificates.take()
     }
+
+    /// The OCSP response stapled by the server, if any
+    pub fn get_ocsp_response(&mut self) -> Option<Vec<u8>> {
+        self.ocsp_response.take()
+    }
+
+    /// The result of the WebPKI verification, even when `insecure_skip_verify` is set
+    pub fn get_verification(&mut self) -> Option<Result<(), VerifyReason>> {
+        self.verification.take()
+    }
 }
 
 impl ServerCertVerifier for CertificateInterceptor {
@@ -60,17 +84,30 @@ impl ServerCertVerifier for CertificateInterceptor {
         certs.extend(intermediates.iter().map(|cert| cert.clone().into_owned()));
 
         self.certificates.set(certs).ok();
+        if !ocsp_response.is_empty() {
+            self.ocsp_response.set(ocsp_response.to_vec()).ok();
+        }
+
+        let result = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        self.verification
+            .set(
+                result
+                    .as_ref()
+                    .map(|_| ())
+                    .map_err(VerifyReason::from_rustls_error),
+            )
+            .ok();
 
         if self.insecure_skip_verify {
             Ok(ServerCertVerified::assertion())
         } else {
-            self.verifier.verify_server_cert(
-                end_entity,
-                intermediates,
-                server_name,
-                ocsp_response,
-                now,
-            )
+            result
         }
     }
 
@@ -0,0 +1,265 @@
+use super::probe_scheduler::{ProbeScheduler, SchedulerCommand};
+use crate::{
+    configs::{GlobalConfig, MetricsConfig, CONFIG_FILE_EXTENSIONS, CONFIG_FILE_NAMES},
+    error::ErrorReason,
+};
+use anyhow::Result as AnyResult;
+use chrono::Utc;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use prometheus::{IntGauge, Opts, Registry};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::sleep,
+};
+
+/// Editors usually write a file several times when saving, so wait for them to finish
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct ReloadRequest {
+    reply: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// Used to ask the [`ConfigReloader`] to reload the configuration.
+#[derive(Clone, Debug)]
+pub struct ReloadHandle {
+    sender: mpsc::Sender<ReloadRequest>,
+}
+
+impl ReloadHandle {
+    /// Reload the configuration and wait for the result.
+    pub async fn reload(&self) -> Result<(), String> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(ReloadRequest { reply: Some(reply) })
+            .await
+            .map_err(|_| ErrorReason::ComponentStopped.to_string())?;
+        result
+            .await
+            .map_err(|_| ErrorReason::ComponentStopped.to_string())?
+    }
+
+    /// Request a reload without waiting for it.
+    ///
+    /// Nothing needs to be done if a request is already pending, since the pending one will
+    /// pick up the latest configuration as well.
+    fn trigger(&self) {
+        self.sender.try_send(ReloadRequest { reply: None }).ok();
+    }
+}
+
+/// Reload the targets when receiving SIGHUP, when the configuration files change, or when
+/// requested through a [`ReloadHandle`].
+///
+/// Only the targets, the file sources and the scheduler configuration are reloaded. Other settings, like the web
+/// interface and the modules, require a restart.
+#[derive(Debug)]
+pub struct ConfigReloader {
+    scheduler: mpsc::Sender<SchedulerCommand>,
+    handle: ReloadHandle,
+    requests: mpsc::Receiver<ReloadRequest>,
+    last_reload_successful: IntGauge,
+    last_reload_success_timestamp: IntGauge,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        scheduler: mpsc::Sender<SchedulerCommand>,
+        registry: &Registry,
+        metrics_config: &MetricsConfig,
+    ) -> AnyResult<Self> {
+        let config_subsystem = metrics_config.family_subsystem("config");
+        let last_reload_successful = IntGauge::with_opts(
+            Opts::new(
+                "last_reload_successful",
+                "Whether the last configuration reload attempt was successful",
+            )
+            .namespace(metrics_config.namespace())
+            .subsystem(config_subsystem.as_str()),
+        )?;
+        registry.register(Box::new(last_reload_successful.clone()))?;
+        let last_reload_success_timestamp = IntGauge::with_opts(
+            Opts::new(
+                "last_reload_success_timestamp_seconds",
+                "Timestamp of the last successful configuration reload",
+            )
+            .namespace(metrics_config.namespace())
+            .subsystem(config_subsystem.as_str()),
+        )?;
+        registry.register(Box::new(last_reload_success_timestamp.clone()))?;
+
+        // The configuration was loaded successfully on startup
+        last_reload_successful.set(1);
+        last_reload_success_timestamp.set(Utc::now().timestamp());
+
+        let (sender, requests) = mpsc::channel(1);
+        Ok(Self {
+            scheduler,
+            handle: ReloadHandle { sender },
+            requests,
+            last_reload_successful,
+            last_reload_success_timestamp,
+        })
+    }
+
+    pub fn handle(&self) -> ReloadHandle {
+        self.handle.clone()
+    }
+
+    pub async fn run(mut self, mut shutdown: watch::Receiver<()>) -> AnyResult<()> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let handle = self.handle();
+            let mut hangup = signal(SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    info!("Received SIGHUP");
+                    handle.trigger();
+                }
+            });
+        }
+
+        // Dropping the watcher stops watching, so keep it until the reloader exits
+        let _watcher = self.watch_config_files();
+
+        loop {
+            let request = tokio::select! {
+                Some(request) = self.requests.recv() => request,
+                _ = shutdown.changed() => break,
+            };
+            let mut replies: Vec<_> = request.reply.into_iter().collect();
+            if replies.is_empty() {
+                sleep(DEBOUNCE_DURATION).await;
+            }
+            while let Ok(request) = self.requests.try_recv() {
+                replies.extend(request.reply);
+            }
+
+            let result = self.reload().await.map_err(|e| format!("{:#}", e));
+            for reply in replies {
+                reply.send(result.clone()).ok();
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reload(&self) -> AnyResult<()> {
+        info!("Reloading the configuration");
+        match self.load_targets().await {
+            Ok(()) => {
+                info!("Configuration reloaded");
+                self.last_reload_successful.set(1);
+                self.last_reload_success_timestamp
+                    .set(Utc::now().timestamp());
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "Failed to reload the configuration, keep the old one: {:#}",
+                    e
+                );
+                self.last_reload_successful.set(0);
+                Err(e)
+            }
+        }
+    }
+
+    async fn load_targets(&self) -> AnyResult<()> {
+        let app_config = GlobalConfig::load_config()?;
+        let targets = ProbeScheduler::load_targets(&app_config.targets).await?;
+        let file_sources = ProbeScheduler::load_file_sources(&app_config.files)?;
+
+        self.scheduler
+            .send(SchedulerCommand::Reload {
+                config: app_config.scheduler,
+                targets,
+                file_sources,
+            })
+            .await
+            .map_err(|_| ErrorReason::ComponentStopped)?;
+        Ok(())
+    }
+
+    /// Watch the directories containing the configuration files, so that files which don't
+    /// exist yet or are replaced by editors are noticed as well.
+    fn watch_config_files(&self) -> Option<RecommendedWatcher> {
+        let handle = self.handle();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) => {
+                    if !matches!(event.kind, EventKind::Access(_))
+                        && event.paths.iter().any(|path| is_config_file(path))
+                    {
+                        debug!("Configuration file changed: {:?}", event.paths);
+                        handle.trigger();
+                    }
+                }
+                Err(e) => warn!("Failed to watch the configuration files: {}", e),
+            })
+            .map_err(|e| warn!("Failed to watch the configuration files: {}", e))
+            .ok()?;
+
+        let directories: HashSet<PathBuf> = CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| config_directory(Path::new(name)))
+            .collect();
+        for directory in directories {
+            if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                debug!("Not watching {}: {}", directory.display(), e);
+            }
+        }
+
+        Some(watcher)
+    }
+}
+
+fn config_directory(name: &Path) -> PathBuf {
+    match name.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn is_config_file(path: &Path) -> bool {
+    let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+        return false;
+    };
+
+    CONFIG_FILE_NAMES
+        .iter()
+        .any(|name| Path::new(name).file_name() == Some(stem))
+        && CONFIG_FILE_EXTENSIONS.iter().any(|ext| extension == *ext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_config_files() {
+        assert!(is_config_file(Path::new("./config.yaml")));
+        assert!(is_config_file(Path::new(
+            "/etc/tls-certificate-exporter.toml"
+        )));
+        assert!(!is_config_file(Path::new("./config.yaml.swp")));
+        assert!(!is_config_file(Path::new("./other.yaml")));
+        assert!(!is_config_file(Path::new("./config")));
+    }
+
+    #[test]
+    fn config_directories() {
+        assert_eq!(config_directory(Path::new("config")), Path::new("."));
+        assert_eq!(
+            config_directory(Path::new("/etc/tls-certificate-exporter")),
+            Path::new("/etc")
+        );
+    }
+}
@@ -1,121 +1,554 @@
-use crate::store::Store;
-use anyhow::Result as AnyResult;
-use axum::{extract::State, http::StatusCode, routing::get, Router};
-use prometheus::{IntGaugeVec, Opts, Registry, TextEncoder};
-use std::{net::Ipv4Addr, sync::Arc};
-use tokio::{net::TcpListener, sync::RwLock};
+use super::{ReloadHandle, StoreCollector};
+use crate::{
+    configs::{ConnectionParameters, MetricsConfig, WebConfig},
+    error::{ErrorClass, ErrorReason},
+    prober::{ProbeError, Prober},
+    store::{Store, Target},
+};
+use anyhow::{Context, Result as AnyResult};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Router,
+};
+use futures::future::try_join_all;
+use prometheus::{proto::MetricFamily, Registry, TextEncoder};
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap, io::Result as IoResult, net::SocketAddr, sync::Arc, time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{watch, RwLock},
+    time::timeout,
+};
+
+/// Used when Prometheus doesn't tell us its scrape timeout
+const DEFAULT_SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The backlog of the listening sockets
+const LISTEN_BACKLOG: i32 = 1024;
+/// Leave some time for encoding and sending the response before Prometheus gives up
+const SCRAPE_TIMEOUT_OFFSET: Duration = Duration::from_millis(500);
+const SCRAPE_TIMEOUT_HEADER: &str = "X-Prometheus-Scrape-Timeout-Seconds";
 
 #[derive(Clone, Debug)]
 struct ExporterState {
     pub store: Arc<RwLock<Store>>,
+    pub prober: Arc<Prober>,
+    pub modules: Arc<HashMap<String, ConnectionParameters>>,
+    pub metrics_config: Arc<MetricsConfig>,
+    /// The metrics which are not derived from the store
     pub registry: Registry,
-    pub metric_not_before: IntGaugeVec,
-    pub metric_not_after: IntGaugeVec,
+    pub reload: Option<ReloadHandle>,
 }
 
 #[derive(Clone, Debug)]
 pub struct MetricsExporter {
     state: ExporterState,
+    config: WebConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeQuery {
+    target: String,
+    module: Option<String>,
 }
 
 impl MetricsExporter {
-    pub fn new(store: Arc<RwLock<Store>>) -> AnyResult<Self> {
+    pub fn new(
+        store: Arc<RwLock<Store>>,
+        prober: Arc<Prober>,
+        modules: HashMap<String, ConnectionParameters>,
+        config: WebConfig,
+        metrics_config: MetricsConfig,
+    ) -> AnyResult<Self> {
+        if !config.metrics_path.starts_with('/')
+            || config.metrics_path == "/probe"
+            || config.metrics_path == "/-/reload"
+        {
+            return Err(ErrorReason::InvalidMetricsPath.into());
+        }
+
         let registry = Registry::new_custom(None, None)?;
-        let cert_labels = ["target", "endpoint", "serial_number", "subject", "issuer"];
-
-        let metric_not_before = IntGaugeVec::new(
-            Opts::new("not_before", "Certificate not before timestamp")
-                .namespace("tlsce")
-                .subsystem("cert"),
-            &cert_labels,
-        )?;
-        registry.register(Box::new(metric_not_before.clone()))?;
-        let metric_not_after = IntGaugeVec::new(
-            Opts::new("not_after", "Certificate not after timestamp")
-                .namespace("tlsce")
-                .subsystem("cert"),
-            &cert_labels,
-        )?;
-        registry.register(Box::new(metric_not_after.clone()))?;
 
         Ok(Self {
             state: ExporterState {
                 store,
+                prober,
+                modules: Arc::new(modules),
+                metrics_config: Arc::new(metrics_config),
                 registry,
-                metric_not_before,
-                metric_not_after,
+                reload: None,
             },
+            config,
         })
     }
 
-    pub async fn run(&self) -> AnyResult<()> {
-        let router = Router::new()
-            .route("/metrics", get(Self::handle_metrics))
-            .with_state(self.state.clone());
+    pub fn registry(&self) -> &Registry {
+        &self.state.registry
+    }
+
+    /// Enable the `/-/reload` endpoint.
+    pub fn with_reload_handle(mut self, reload: ReloadHandle) -> Self {
+        self.state.reload = Some(reload);
+        self
+    }
+
+    fn router(&self) -> Router {
+        Router::new()
+            .route(&self.config.metrics_path, get(Self::handle_metrics))
+            .route("/probe", get(Self::handle_probe))
+            .route("/-/reload", post(Self::handle_reload))
+            .with_state(self.state.clone())
+    }
 
-        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 9880)).await?;
-        axum::serve(listener, router).await?;
+    /// Serve until `shutdown` is notified, then wait for the pending requests to finish.
+    pub async fn run(&self, shutdown: watch::Receiver<()>) -> AnyResult<()> {
+        let router = self.router();
+
+        let listeners = self
+            .config
+            .listen_addresses
+            .iter()
+            .map(|addr| {
+                let sockaddr = SocketAddr::new(*addr, self.config.port);
+                info!("Listening on {}", sockaddr);
+                bind(sockaddr).with_context(|| format!("Failed to listen on {}", sockaddr))
+            })
+            .collect::<AnyResult<Vec<_>>>()?;
+
+        try_join_all(listeners.into_iter().map(|listener| {
+            let router = router.clone();
+            let mut shutdown = shutdown.clone();
+            async move {
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(async move {
+                        shutdown.changed().await.ok();
+                    })
+                    .await
+            }
+        }))
+        .await?;
         Ok(())
     }
 
     async fn handle_metrics(state: State<ExporterState>) -> Result<String, StatusCode> {
-        let store = state.store.read().await;
-
-        for ep_state in store.endpoint_store.values() {
-            for cert_id in &ep_state.cert_idents {
-                let Some(cert) = store.cert_store.get(cert_id) else {
-                    continue;
-                };
-
-                let label_values = [
-                    ep_state
-                        .target
-                        .as_ref()
-                        .map(|target| target.to_string())
-                        .unwrap_or_default(),
-                    ep_state.endpoint.to_string(),
-                    cert.serial_number().to_string(),
-                    cert.subject_common_name().unwrap_or_default(),
-                    cert.issuer_common_name().unwrap_or_default(),
-                ];
-                // FIXME: Wait for array::each_ref() to be stabilized
-                let label_values_ref = [
-                    label_values[0].as_str(),
-                    label_values[1].as_str(),
-                    label_values[2].as_str(),
-                    label_values[3].as_str(),
-                    label_values[4].as_str(),
-                ];
-
-                let not_before = cert.not_before();
-                let not_after = cert.not_after();
-
-                match state
-                    .metric_not_before
-                    .get_metric_with_label_values(&label_values_ref)
-                {
-                    Ok(metric) => metric.set(not_before),
-                    Err(e) => {
-                        error!("Failed to get metric: {}", e);
-                    }
-                }
-                match state
-                    .metric_not_after
-                    .get_metric_with_label_values(&label_values_ref)
-                {
-                    Ok(metric) => metric.set(not_after),
-                    Err(e) => {
-                        error!("Failed to get metric: {}", e);
-                    }
-                }
+        let collector = {
+            let store = state.store.read().await;
+            StoreCollector::from_store(&store, &state.metrics_config)
+        }
+        .map_err(|e| {
+            error!("Failed to collect metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let mut metric_families = state.registry.gather();
+        metric_families.extend(gather(collector)?);
+        encode_metric_families(metric_families)
+    }
+
+    async fn handle_reload(state: State<ExporterState>) -> Result<String, (StatusCode, String)> {
+        let reload = state.reload.as_ref().ok_or((
+            StatusCode::NOT_FOUND,
+            "Configuration reload is not enabled".to_owned(),
+        ))?;
+        reload
+            .reload()
+            .await
+            .map(|_| "Configuration reloaded\n".to_owned())
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+    }
+
+    /// Probe the requested target on demand, like the blackbox exporter does.
+    ///
+    /// The results are neither stored nor exported from `/metrics`.
+    async fn handle_probe(
+        state: State<ExporterState>,
+        Query(query): Query<ProbeQuery>,
+        headers: HeaderMap,
+    ) -> Result<String, (StatusCode, String)> {
+        let target: Target = query.target.parse().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid target: {}", &query.target),
+            )
+        })?;
+
+        let default_params = ConnectionParameters::default();
+        let params = match query.module.as_deref() {
+            Some(module) => state.modules.get(module).ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown module: {}", module),
+                )
+            })?,
+            None => &default_params,
+        };
+
+        let probe_timeout = scrape_timeout(&headers)
+            .unwrap_or(DEFAULT_SCRAPE_TIMEOUT)
+            .saturating_sub(SCRAPE_TIMEOUT_OFFSET);
+        // The resolution, the connection and the handshake followed by the scan take up to the
+        // timeout each, so that the probe finishes before the scrape times out
+        let params = ConnectionParameters {
+            timeout: Some(state.prober.timeout(params).min(probe_timeout / 3)),
+            ..params.clone()
+        };
+        // Failures are reported by the metrics, like the blackbox exporter
+        let mut store = Store::default();
+        match timeout(probe_timeout, state.prober.probe(&target, &params)).await {
+            Ok(Ok(probe_results)) => {
+                store
+                    .update_probe_result(&target, probe_results)
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to probe the target {}: {}", &target, e);
+                store.update_probe_error(&target, ProbeError::from_error(&e));
             }
+            Err(_) => {
+                warn!("Probing the target {} timed out", &target);
+                store.update_probe_error(
+                    &target,
+                    ProbeError {
+                        class: ErrorClass::Timeout,
+                        message: "Probe timeout".to_owned(),
+                    },
+                );
+            }
+        };
+
+        let collector = StoreCollector::from_store(&store, &state.metrics_config)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        gather(collector)
+            .and_then(encode_metric_families)
+            .map_err(|status| (status, String::new()))
+    }
+}
+
+/// Bind the address with `IPV6_V6ONLY`, so that IPv4 and IPv6 wildcard addresses
+/// can be listened at the same time.
+fn bind(sockaddr: SocketAddr) -> IoResult<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(sockaddr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if sockaddr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&sockaddr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Gather the metrics of a collector, with the same checks as a registry does.
+fn gather(collector: StoreCollector) -> Result<Vec<MetricFamily>, StatusCode> {
+    let registry =
+        Registry::new_custom(None, None).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    registry.register(Box::new(collector)).map_err(|e| {
+        error!("Failed to register the collector: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(registry.gather())
+}
+
+fn encode_metric_families(mut metric_families: Vec<MetricFamily>) -> Result<String, StatusCode> {
+    metric_families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+    let encoder = TextEncoder::new();
+    encoder
+        .encode_to_string(&metric_families)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn scrape_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let secs: f64 = headers
+        .get(SCRAPE_TIMEOUT_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{certificate, TlsServer};
+    use axum::{
+        body::{to_bytes, Body},
+        http::Request,
+    };
+    use chrono::{Duration as ChronoDuration, Utc};
+    use hickory_resolver::TokioAsyncResolver;
+    use tokio::time::Instant;
+    use tower::ServiceExt;
+
+    /// The defaults trust an unrelated certificate, so that only the modules can verify a chain
+    fn exporter(modules: HashMap<String, ConnectionParameters>) -> MetricsExporter {
+        let resolver = Arc::new(TokioAsyncResolver::tokio_from_system_conf().unwrap());
+        let mut default_params = ConnectionParameters::default();
+        default_params
+            .trusted_anchors
+            .add(certificate(&[], Utc::now() + ChronoDuration::hours(1)).0)
+            .unwrap();
+        MetricsExporter::new(
+            Arc::new(RwLock::new(Store::default())),
+            Arc::new(Prober::new(resolver, default_params)),
+            modules,
+            WebConfig::default(),
+            MetricsConfig::default(),
+        )
+        .unwrap()
+    }
+
+    async fn get(
+        exporter: &MetricsExporter,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, String) {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = exporter
+            .router()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// The values of the series of a family which have all the labels
+    fn values(body: &str, name: &str, labels: &[&str]) -> Vec<f64> {
+        body.lines()
+            .filter(|line| line.starts_with(&format!("{}{{", name)))
+            .filter(|line| labels.iter().all(|label| line.contains(label)))
+            .filter_map(|line| line.rsplit(' ').next()?.parse().ok())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn invalid_metrics_path() {
+        let resolver = Arc::new(TokioAsyncResolver::tokio_from_system_conf().unwrap());
+        let prober = Arc::new(Prober::new(resolver, ConnectionParameters::default()));
+        let new_exporter = |metrics_path: &str| {
+            MetricsExporter::new(
+                Arc::new(RwLock::new(Store::default())),
+                prober.clone(),
+                HashMap::new(),
+                WebConfig {
+                    metrics_path: metrics_path.to_owned(),
+                    ..Default::default()
+                },
+                MetricsConfig::default(),
+            )
+        };
+
+        for metrics_path in ["metrics", "/probe", "/-/reload"] {
+            let err = new_exporter(metrics_path).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ErrorReason>(),
+                    Some(ErrorReason::InvalidMetricsPath)
+                ),
+                "{}",
+                metrics_path
+            );
+        }
+        new_exporter("/custom/metrics").unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_dual_stack() {
+        let v6 = bind("[::]:0".parse().unwrap()).unwrap();
+        assert!(socket2::SockRef::from(&v6).only_v6().unwrap());
+        // The IPv4 wildcard address is still free on the same port
+        let port = v6.local_addr().unwrap().port();
+        let v4 = bind(SocketAddr::from(([0, 0, 0, 0], port))).unwrap();
+
+        tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (_, peer) = v4.accept().await.unwrap();
+        assert!(peer.is_ipv4());
+        tokio::net::TcpStream::connect(("::1", port)).await.unwrap();
+        let (_, peer) = v6.accept().await.unwrap();
+        assert!(peer.is_ipv6());
+    }
+
+    #[tokio::test]
+    async fn run_fails_to_bind() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut exporter = exporter(HashMap::new());
+        exporter.config = WebConfig {
+            listen_addresses: vec!["127.0.0.1".parse().unwrap()],
+            port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+
+        let (_shutdown_tx, shutdown) = watch::channel(());
+        assert!(exporter.run(shutdown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stop_on_shutdown() {
+        let mut exporter = exporter(HashMap::new());
+        exporter.config = WebConfig {
+            listen_addresses: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+            port: 0,
+            ..Default::default()
+        };
+
+        let (shutdown_tx, shutdown) = watch::channel(());
+        let run = tokio::spawn(async move { exporter.run(shutdown).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send(()).unwrap();
+        timeout(Duration::from_secs(1), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn probe_invalid_request() {
+        let exporter = exporter(HashMap::new());
+        for uri in [
+            "/probe",
+            "/probe?target=example.com",
+            "/probe?target=example.com:https",
+            "/probe?target=127.0.0.1:443&module=unknown",
+        ] {
+            let (status, _) = get(&exporter, uri, &[]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_with_module() {
+        let server = TlsServer {
+            subject_alt_names: vec!["127.0.0.1"],
+            handshakes: 2,
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let mut module = ConnectionParameters::default();
+        module.trusted_anchors.add(server.cert.clone()).unwrap();
+        let exporter = exporter(HashMap::from([("internal".to_owned(), module)]));
+        let target = format!("target=\"{}\"", server.addr);
+
+        let uri = format!("/probe?target={}&module=internal", server.addr);
+        let (status, body) = get(&exporter, &uri, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(values(&body, "tlsce_probe_success", &[&target]), [1.0]);
+        assert_eq!(
+            values(&body, "tlsce_chain_verified", &[&target, "reason=\"\""]),
+            [1.0]
+        );
+        assert_eq!(values(&body, "tlsce_cert_not_after", &[&target]).len(), 1);
+        // Only `/probe` exports the result
+        let (_, metrics) = get(&exporter, "/metrics", &[]).await;
+        assert!(values(&metrics, "tlsce_probe_success", &[&target]).is_empty());
+
+        // Without the module, the certificate isn't trusted
+        let uri = format!("/probe?target={}", server.addr);
+        let (status, body) = get(&exporter, &uri, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            values(
+                &body,
+                "tlsce_chain_verified",
+                &[&target, "reason=\"unknown_issuer\""]
+            ),
+            [0.0]
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_insecure_skip_verify() {
+        let untrusted = TlsServer {
+            subject_alt_names: vec!["127.0.0.1"],
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let expired = TlsServer {
+            subject_alt_names: vec!["127.0.0.1"],
+            not_after: Utc::now() - ChronoDuration::hours(1),
+            ..Default::default()
         }
+        .start()
+        .await;
+        let mut module = ConnectionParameters {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        module.trusted_anchors.add(expired.cert.clone()).unwrap();
+        let exporter = exporter(HashMap::from([("insecure".to_owned(), module)]));
 
-        let encoder = TextEncoder::new();
-        let resp = encoder
-            .encode_to_string(&state.registry.gather())
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // The handshakes succeed, while the chains are still reported as not verified
+        for (server, reason) in [(untrusted, "unknown_issuer"), (expired, "expired")] {
+            let target = format!("target=\"{}\"", server.addr);
+            let uri = format!("/probe?target={}&module=insecure", server.addr);
+            let (status, body) = get(&exporter, &uri, &[]).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(values(&body, "tlsce_probe_success", &[&target]), [1.0]);
+            assert_eq!(
+                values(
+                    &body,
+                    "tlsce_chain_verified",
+                    &[&target, &format!("reason=\"{}\"", reason)]
+                ),
+                [0.0],
+                "{}",
+                reason
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_within_the_scrape_timeout() {
+        // The server never answers the handshake
+        let server = TlsServer {
+            handshakes: 0,
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let module = ConnectionParameters {
+            timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let exporter = exporter(HashMap::from([("slow".to_owned(), module)]));
 
-        Ok(resp)
+        let start = Instant::now();
+        let uri = format!("/probe?target={}&module=slow", server.addr);
+        let (status, body) = get(&exporter, &uri, &[(SCRAPE_TIMEOUT_HEADER, "1.5")]).await;
+        assert_eq!(status, StatusCode::OK);
+        // The handshake times out after a third of the 1s left for the probe, so the endpoint
+        // is reported instead of the target only
+        assert!(start.elapsed() < Duration::from_millis(900));
+        let endpoint = format!("endpoint=\"{}\"", server.addr);
+        assert_eq!(
+            values(
+                &body,
+                "tlsce_probe_error",
+                &[&endpoint, "class=\"timeout\""]
+            ),
+            [1.0]
+        );
+        assert_eq!(values(&body, "tlsce_probe_success", &[&endpoint]), [0.0]);
     }
 }
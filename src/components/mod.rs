@@ -1,5 +1,9 @@
+mod config_reloader;
 mod metrics_exporter;
 mod probe_scheduler;
+mod store_collector;
 
+pub use config_reloader::{ConfigReloader, ReloadHandle};
 pub use metrics_exporter::MetricsExporter;
 pub use probe_scheduler::ProbeScheduler;
+pub use store_collector::StoreCollector;
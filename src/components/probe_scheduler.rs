@@ -1,33 +1,167 @@
 use crate::{
     configs::{
-        ConnectionParameters, SchedulerConfig, SchedulerOverrideConfig, TargetConfig,
-        DEFAULT_INTERVAL,
+        ConnectionParameters, FileSourceConfig, SchedulerConfig, SchedulerOverrideConfig,
+        TargetConfig, DEFAULT_INTERVAL,
     },
-    prober::Prober,
-    store::{Store, Target, TargetState},
+    prober::{ProbeError, Prober},
+    store::{FileSource, FileSourceState, Store, Target, TargetState},
 };
 use anyhow::Result as AnyResult;
 use chrono::Utc;
 use futures::prelude::*;
 use futures::stream::FuturesUnordered;
 use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{sync::RwLock, time::sleep};
+use tokio::{
+    sync::{mpsc, watch, RwLock},
+    time::sleep,
+};
+
+#[derive(Debug)]
+pub enum SchedulerCommand {
+    /// Replace the scheduler configuration, the set of targets and the file sources
+    Reload {
+        config: SchedulerConfig,
+        targets: HashMap<Target, TargetState>,
+        file_sources: HashMap<FileSource, FileSourceState>,
+    },
+}
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct ProbeScheduler {
     prober: Arc<Prober>,
     store: Arc<RwLock<Store>>,
     config: SchedulerConfig,
     target_store: HashMap<Target, TargetState>,
+    file_sources: HashMap<FileSource, FileSourceState>,
+    command_tx: mpsc::Sender<SchedulerCommand>,
+    command_rx: mpsc::Receiver<SchedulerCommand>,
 }
 
 impl ProbeScheduler {
     pub fn new(prober: Arc<Prober>, store: Arc<RwLock<Store>>, config: SchedulerConfig) -> Self {
+        let (command_tx, command_rx) = mpsc::channel(4);
         Self {
             prober,
             store,
             config,
             target_store: Default::default(),
+            file_sources: Default::default(),
+            command_tx,
+            command_rx,
+        }
+    }
+
+    /// Return a sender which can be used to control the scheduler while it is running.
+    pub fn command_sender(&self) -> mpsc::Sender<SchedulerCommand> {
+        self.command_tx.clone()
+    }
+
+    /// Load the states of targets from the configuration without adding them to the scheduler.
+    pub async fn load_targets(
+        target_configs: &[TargetConfig],
+    ) -> AnyResult<HashMap<Target, TargetState>> {
+        let mut targets = HashMap::new();
+        for target_config in target_configs {
+            let conn_params = ConnectionParameters::load_from_target_config(target_config).await?;
+            targets.insert(
+                target_config.target.parse()?,
+                TargetState {
+                    conn_params,
+                    schedule_config: target_config.schedule_config.clone(),
+                    ..Default::default()
+                },
+            );
+        }
+        Ok(targets)
+    }
+
+    /// Load the file sources from the configuration without adding them to the scheduler.
+    pub fn load_file_sources(
+        file_configs: &[FileSourceConfig],
+    ) -> AnyResult<HashMap<FileSource, FileSourceState>> {
+        let mut file_sources = HashMap::new();
+        for file_config in file_configs {
+            file_sources.insert(
+                file_config.path.parse()?,
+                FileSourceState {
+                    schedule_config: file_config.schedule_config.clone(),
+                    password: file_config.password.clone(),
+                    ..Default::default()
+                },
+            );
+        }
+        Ok(file_sources)
+    }
+
+    /// Replace the current targets with the given ones.
+    ///
+    /// Targets which still exist keep their schedule, while removed targets are also dropped
+    /// from the store.
+    pub async fn update_targets(
+        &mut self,
+        config: SchedulerConfig,
+        targets: HashMap<Target, TargetState>,
+    ) {
+        self.config = config;
+
+        let removed: Vec<Target> = self
+            .target_store
+            .keys()
+            .filter(|target| !targets.contains_key(target))
+            .cloned()
+            .collect();
+        if !removed.is_empty() {
+            let mut store = self.store.write().await;
+            for target in &removed {
+                info!("Remove target: {}", target);
+                self.target_store.remove(target);
+                store.remove_target(target);
+            }
+        }
+
+        for (target, mut state) in targets {
+            self.update_configured_certificates(&target, &state.conn_params)
+                .await;
+            if let Some(old_state) = self.target_store.get(&target) {
+                let config = &state.schedule_config + &self.config;
+                state.last_probe = old_state.last_probe;
+                state.next_probe = old_state.last_probe.map(|last| last + config.interval);
+            } else {
+                info!("Add target: {}", target);
+            }
+            self.target_store.insert(target, state);
+        }
+    }
+
+    /// Replace the current file sources with the given ones, like [`Self::update_targets`].
+    pub async fn update_file_sources(
+        &mut self,
+        file_sources: HashMap<FileSource, FileSourceState>,
+    ) {
+        let removed: Vec<FileSource> = self
+            .file_sources
+            .keys()
+            .filter(|source| !file_sources.contains_key(source))
+            .cloned()
+            .collect();
+        if !removed.is_empty() {
+            let mut store = self.store.write().await;
+            for source in &removed {
+                info!("Remove file source: {}", source);
+                self.file_sources.remove(source);
+                store.remove_file_source(source);
+            }
+        }
+
+        for (source, mut state) in file_sources {
+            if let Some(old_state) = self.file_sources.get(&source) {
+                let config = &state.schedule_config + &self.config;
+                state.last_read = old_state.last_read;
+                state.next_read = old_state.last_read.map(|last| last + config.interval);
+            } else {
+                info!("Add file source: {}", source);
+            }
+            self.file_sources.insert(source, state);
         }
     }
 
@@ -50,12 +184,64 @@ impl ProbeScheduler {
     pub async fn load_from_target_config(&mut self, target_config: &TargetConfig) -> AnyResult<()> {
         let conn_params = ConnectionParameters::load_from_target_config(target_config).await?;
         let schedule_config = target_config.schedule_config.clone();
+        let target = target_config.target.parse()?;
 
-        self.add_target(target_config.target.parse()?, conn_params, schedule_config);
+        self.update_configured_certificates(&target, &conn_params)
+            .await;
+        self.add_target(target, conn_params, schedule_config);
 
         Ok(())
     }
 
+    /// Export the trust anchors and client certificates configured for the target.
+    async fn update_configured_certificates(
+        &self,
+        target: &Target,
+        conn_params: &ConnectionParameters,
+    ) {
+        if let Err(e) = self
+            .store
+            .write()
+            .await
+            .update_configured_certificates(Some(target), conn_params)
+        {
+            warn!(
+                "Failed to parse the configured certificates of {}: {:#}",
+                target, e
+            );
+        }
+    }
+
+    /// Read the file sources which are due, and update their certificates in the store.
+    async fn read_file_sources(&mut self) {
+        let now = Utc::now();
+        for (source, state) in self.file_sources.iter_mut() {
+            if state.next_read.map_or(false, |next_read| now < next_read) {
+                continue;
+            }
+
+            let password = match state.password.load().await {
+                Ok(password) => password,
+                Err(e) => {
+                    error!("Failed to read the keystore password of {}: {}", source, e);
+                    None
+                }
+            };
+            match source.read_files().await {
+                Ok(files) => {
+                    self.store
+                        .write()
+                        .await
+                        .update_file_source(source, files, password.as_deref())
+                }
+                Err(e) => error!("Failed to read the file source {}: {:#}", source, e),
+            }
+            let config = &state.schedule_config + &self.config;
+            state.last_read = Some(Utc::now());
+            state.next_read = Some(Utc::now() + config.interval);
+        }
+    }
+
     pub fn iter_need_probe(&self) -> impl Iterator<Item = (&Target, &TargetState)> {
         self.target_store.iter().filter(|(_target, state)| {
             if let Some(next_probe) = state.next_probe {
@@ -72,9 +258,11 @@ impl ProbeScheduler {
 
         self.target_store
             .values()
-            .fold(DEFAULT_INTERVAL, |dura, v| {
-                let nextdura = if let Some(next_probe) = v.next_probe {
-                    (next_probe - now).to_std().unwrap_or(Duration::ZERO)
+            .map(|state| state.next_probe)
+            .chain(self.file_sources.values().map(|state| state.next_read))
+            .fold(DEFAULT_INTERVAL, |dura, next| {
+                let nextdura = if let Some(next) = next {
+                    (next - now).to_std().unwrap_or(Duration::ZERO)
                 } else {
                     Duration::ZERO
                 };
@@ -83,11 +271,31 @@ impl ProbeScheduler {
             })
     }
 
-    pub async fn run(&mut self) -> AnyResult<()> {
+    /// Probe the targets until `shutdown` is notified.
+    ///
+    /// Probes which are in progress are finished before returning.
+    pub async fn run(&mut self, mut shutdown: watch::Receiver<()>) -> AnyResult<()> {
         loop {
             let wait = self.wait_duration();
             debug!("Sleep for: {}ms", wait.as_millis());
-            sleep(wait).await;
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = shutdown.changed() => {
+                    info!("Stop scheduling probes");
+                    return Ok(());
+                }
+                Some(command) = self.command_rx.recv() => {
+                    match command {
+                        SchedulerCommand::Reload { config, targets, file_sources } => {
+                            self.update_targets(config, targets).await;
+                            self.update_file_sources(file_sources).await;
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            self.read_file_sources().await;
 
             let targets: Vec<(Target, ConnectionParameters)> = self
                 .iter_need_probe()
@@ -122,6 +330,10 @@ impl ProbeScheduler {
                     }
                     Err(e) => {
                         error!("Failed to probe the target {}: {}", &target, e);
+                        self.store
+                            .write()
+                            .await
+                            .update_probe_error(&target, ProbeError::from_error(&e));
 
                         if let Some(state) = state {
                             // TODO: Add backoff interval config
@@ -135,3 +347,32 @@ impl ProbeScheduler {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hickory_resolver::TokioAsyncResolver;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn stop_on_shutdown() {
+        let resolver = Arc::new(TokioAsyncResolver::tokio_from_system_conf().unwrap());
+        let prober = Arc::new(Prober::new(resolver, ConnectionParameters::default()));
+        let mut scheduler = ProbeScheduler::new(
+            prober,
+            Arc::new(RwLock::new(Store::default())),
+            SchedulerConfig::default(),
+        );
+
+        let (shutdown_tx, shutdown) = watch::channel(());
+        let run = tokio::spawn(async move { scheduler.run(shutdown).await });
+        // Let the scheduler wait for the next probe first
+        sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send(()).unwrap();
+        timeout(Duration::from_secs(1), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,1166 @@
+use crate::{
+    cert::{CertificateRole, OcspCertStatus, OcspResponse, ParsedCertificate},
+    configs::MetricsConfig,
+    error::ErrorClass,
+    prober::ProbeError,
+    store::{CertificateSource, EndpointState, LeafDetails, Store},
+};
+use anyhow::Result as AnyResult;
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    GaugeVec, IntGaugeVec, Opts,
+};
+use std::collections::HashSet;
+
+/// Export the metrics from a snapshot of the store.
+///
+/// A new collector is built on every scrape, so that the series of removed endpoints and
+/// replaced certificates disappear with them.
+#[derive(Clone, Debug)]
+pub struct StoreCollector {
+    cert_metrics: CertificateMetrics,
+    probe_metrics: ProbeMetrics,
+    crl_metrics: CrlMetrics,
+}
+
+impl StoreCollector {
+    pub fn from_store(store: &Store, config: &MetricsConfig) -> AnyResult<Self> {
+        let cert_metrics = CertificateMetrics::new(config)?;
+        cert_metrics.update(store);
+        let probe_metrics = ProbeMetrics::new(config)?;
+        probe_metrics.update(store);
+        let crl_metrics = CrlMetrics::new(config)?;
+        crl_metrics.update(store);
+
+        Ok(Self {
+            cert_metrics,
+            probe_metrics,
+            crl_metrics,
+        })
+    }
+
+    fn collectors(&self) -> [&dyn Collector; 27] {
+        [
+            &self.cert_metrics.not_before,
+            &self.cert_metrics.not_after,
+            &self.cert_metrics.file_not_before,
+            &self.cert_metrics.file_not_after,
+            &self.cert_metrics.configured_not_before,
+            &self.cert_metrics.configured_not_after,
+            &self.cert_metrics.revoked,
+            &self.cert_metrics.info,
+            &self.cert_metrics.sans,
+            &self.cert_metrics.hostname_match,
+            &self.cert_metrics.sct_count,
+            &self.cert_metrics.sct_timestamp,
+            &self.cert_metrics.ct_compliant,
+            &self.probe_metrics.success,
+            &self.probe_metrics.duration,
+            &self.probe_metrics.error,
+            &self.probe_metrics.chain_verified,
+            &self.probe_metrics.chain_length,
+            &self.probe_metrics.connection_info,
+            &self.probe_metrics.supported,
+            &self.probe_metrics.ocsp_stapled,
+            &self.probe_metrics.ocsp_status,
+            &self.probe_metrics.ocsp_this_update,
+            &self.probe_metrics.ocsp_next_update,
+            &self.probe_metrics.ocsp_signature_valid,
+            &self.crl_metrics.this_update,
+            &self.crl_metrics.next_update,
+        ]
+    }
+}
+
+impl Collector for StoreCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.collect())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CertificateMetrics {
+    pub not_before: IntGaugeVec,
+    pub not_after: IntGaugeVec,
+    pub file_not_before: IntGaugeVec,
+    pub file_not_after: IntGaugeVec,
+    pub configured_not_before: IntGaugeVec,
+    pub configured_not_after: IntGaugeVec,
+    pub revoked: IntGaugeVec,
+    pub info: IntGaugeVec,
+    pub sans: IntGaugeVec,
+    pub hostname_match: IntGaugeVec,
+    pub sct_count: IntGaugeVec,
+    pub sct_timestamp: IntGaugeVec,
+    pub ct_compliant: IntGaugeVec,
+}
+
+impl CertificateMetrics {
+    fn new(config: &MetricsConfig) -> AnyResult<Self> {
+        let cert_labels = [
+            "target",
+            "endpoint",
+            "server_name",
+            "serial_number",
+            "subject",
+            "issuer",
+            "chain_index",
+            "role",
+        ]
+        .map(|label| config.label_name(label));
+        let configured_cert_labels = [
+            "target",
+            "source",
+            "chain_index",
+            "role",
+            "serial_number",
+            "subject",
+            "issuer",
+        ]
+        .map(|label| config.label_name(label));
+        // e.g. `tlsce_configured_cert_not_after`
+        let configured_subsystem = format!("configured_{}", config.subsystem());
+        let file_cert_labels = [
+            "file",
+            "alias",
+            "chain_index",
+            "serial_number",
+            "subject",
+            "issuer",
+        ]
+        .map(|label| config.label_name(label));
+        // e.g. `tlsce_file_cert_not_after`
+        let file_subsystem = format!("file_{}", config.subsystem());
+
+        let not_before = IntGaugeVec::new(
+            Opts::new("not_before", "Certificate not before timestamp")
+                .namespace(config.namespace())
+                .subsystem(config.subsystem()),
+            &cert_labels,
+        )?;
+        let not_after = IntGaugeVec::new(
+            Opts::new("not_after", "Certificate not after timestamp")
+                .namespace(config.namespace())
+                .subsystem(config.subsystem()),
+            &cert_labels,
+        )?;
+        let file_not_before = IntGaugeVec::new(
+            Opts::new(
+                "not_before",
+                "Not before timestamp of the certificates read from local files",
+            )
+            .namespace(config.namespace())
+            .subsystem(file_subsystem.as_str()),
+            &file_cert_labels,
+        )?;
+        let file_not_after = IntGaugeVec::new(
+            Opts::new(
+                "not_after",
+                "Not after timestamp of the certificates read from local files",
+            )
+            .namespace(config.namespace())
+            .subsystem(file_subsystem.as_str()),
+            &file_cert_labels,
+        )?;
+        let configured_not_before = IntGaugeVec::new(
+            Opts::new(
+                "not_before",
+                "Not before timestamp of the trust anchors and client certificates of the configuration",
+            )
+            .namespace(config.namespace())
+            .subsystem(configured_subsystem.as_str()),
+            &configured_cert_labels,
+        )?;
+        let configured_not_after = IntGaugeVec::new(
+            Opts::new(
+                "not_after",
+                "Not after timestamp of the trust anchors and client certificates of the configuration",
+            )
+            .namespace(config.namespace())
+            .subsystem(configured_subsystem.as_str()),
+            &configured_cert_labels,
+        )?;
+        let revoked = IntGaugeVec::new(
+            Opts::new(
+                "revoked",
+                "Whether the certificate is revoked by the configured CRLs of its issuer",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &cert_labels,
+        )?;
+
+        let info = IntGaugeVec::new(
+            Opts::new(
+                "info",
+                "Key and signature algorithms of the certificate, always 1",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &[
+                "target",
+                "endpoint",
+                "server_name",
+                "serial_number",
+                "key_algorithm",
+                "key_size",
+                "curve",
+                "signature_algorithm",
+                "fingerprint_sha256",
+            ]
+            .map(|label| config.label_name(label)),
+        )?;
+        let sans = IntGaugeVec::new(
+            Opts::new("sans", "Subject alternative names of the leaf certificate")
+                .namespace(config.namespace())
+                .subsystem(config.subsystem()),
+            &[
+                "target",
+                "endpoint",
+                "server_name",
+                "serial_number",
+                "type",
+                "value",
+            ]
+            .map(|label| config.label_name(label)),
+        )?;
+        let hostname_match = IntGaugeVec::new(
+            Opts::new(
+                "hostname_match",
+                "Whether the leaf certificate is valid for the server name, regardless of the chain",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &["target", "endpoint", "server_name"],
+        )?;
+
+        let leaf_labels = ["target", "endpoint", "server_name", "serial_number"]
+            .map(|label| config.label_name(label));
+        let sct_count = IntGaugeVec::new(
+            Opts::new(
+                "sct_count",
+                "Number of SCTs embedded in the leaf certificate",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &leaf_labels,
+        )?;
+        let sct_timestamp = IntGaugeVec::new(
+            Opts::new(
+                "sct_timestamp",
+                "Timestamp of the SCTs embedded in the leaf certificate",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &[
+                "target",
+                "endpoint",
+                "server_name",
+                "serial_number",
+                "log_id",
+            ]
+            .map(|label| config.label_name(label)),
+        )?;
+        let ct_compliant = IntGaugeVec::new(
+            Opts::new(
+                "ct_compliant",
+                "Whether the leaf certificate has valid SCTs from enough logs of the CT log list",
+            )
+            .namespace(config.namespace())
+            .subsystem(config.subsystem()),
+            &leaf_labels,
+        )?;
+
+        Ok(Self {
+            not_before,
+            not_after,
+            file_not_before,
+            file_not_after,
+            configured_not_before,
+            configured_not_after,
+            revoked,
+            info,
+            sans,
+            hostname_match,
+            sct_count,
+            sct_timestamp,
+            ct_compliant,
+        })
+    }
+
+    fn update(&self, store: &Store) {
+        for ep_state in store.endpoint_store.values() {
+            let leaf = ep_state
+                .cert_idents
+                .first()
+                .and_then(|cert_id| store.cert_store.get(cert_id));
+            if let (Some(leaf), Some(details)) = (leaf, &ep_state.leaf) {
+                self.update_leaf(ep_state, leaf, details);
+            }
+
+            for (chain_index, cert_id) in ep_state.cert_idents.iter().enumerate() {
+                let Some(cert) = store.cert_store.get(cert_id) else {
+                    continue;
+                };
+
+                let label_values = [
+                    ep_state
+                        .target
+                        .as_ref()
+                        .map(|target| target.to_string())
+                        .unwrap_or_default(),
+                    ep_state.endpoint.to_string(),
+                    ep_state.endpoint.server_name.to_str().into_owned(),
+                    cert.serial_number().to_string(),
+                    cert.subject_common_name().unwrap_or_default(),
+                    cert.issuer_common_name().unwrap_or_default(),
+                    chain_index.to_string(),
+                    CertificateRole::in_chain(cert, chain_index)
+                        .as_str()
+                        .to_owned(),
+                ];
+                let label_values_ref: Vec<&str> = label_values.iter().map(String::as_str).collect();
+
+                set_validity(&self.not_before, &self.not_after, &label_values_ref, cert);
+
+                // Only known if one of the CRLs is issued by the issuer of the certificate
+                let revoked = ep_state
+                    .crls
+                    .iter()
+                    .filter_map(|crl| crl.is_revoked(cert))
+                    .reduce(|a, b| a || b);
+                if let Some(revoked) = revoked {
+                    match self.revoked.get_metric_with_label_values(&label_values_ref) {
+                        Ok(metric) => metric.set(revoked.into()),
+                        Err(e) => {
+                            error!("Failed to get metric: {}", e);
+                        }
+                    }
+                }
+
+                let info_values = [
+                    cert.key_algorithm_name(),
+                    cert.key_size()
+                        .map(|size| size.to_string())
+                        .unwrap_or_default(),
+                    cert.key_curve().unwrap_or_default().to_owned(),
+                    cert.signature_algorithm_name(),
+                    cert.sha256_fingerprint_hex().unwrap_or_default(),
+                ];
+                let info_values_ref: Vec<&str> = label_values_ref[..4]
+                    .iter()
+                    .copied()
+                    .chain(info_values.iter().map(String::as_str))
+                    .collect();
+                match self.info.get_metric_with_label_values(&info_values_ref) {
+                    Ok(metric) => metric.set(1),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+        }
+
+        self.update_files(store);
+        self.update_configured(store);
+    }
+
+    /// Export the certificates read from local files, which are not sent in a chain
+    fn update_files(&self, store: &Store) {
+        // A file matched by several sources is only exported once
+        let mut exported = HashSet::new();
+        for ((_, path), file_state) in &store.file_store {
+            if !exported.insert(path) {
+                continue;
+            }
+            let file = path.display().to_string();
+            for entry in &file_state.entries {
+                for (index, cert_id) in entry.cert_idents.iter().enumerate() {
+                    let Some(cert) = store.cert_store.get(cert_id) else {
+                        continue;
+                    };
+
+                    let serial_number = cert.serial_number().to_string();
+                    let subject = cert.subject_common_name().unwrap_or_default();
+                    let issuer = cert.issuer_common_name().unwrap_or_default();
+                    let index = index.to_string();
+                    set_validity(
+                        &self.file_not_before,
+                        &self.file_not_after,
+                        &[
+                            file.as_str(),
+                            entry.alias.as_str(),
+                            index.as_str(),
+                            serial_number.as_str(),
+                            subject.as_str(),
+                            issuer.as_str(),
+                        ],
+                        cert,
+                    );
+                }
+            }
+        }
+    }
+
+    fn update_configured(&self, store: &Store) {
+        for (target, sources) in &store.configured_certs {
+            let target = target
+                .as_ref()
+                .map(|target| target.to_string())
+                .unwrap_or_default();
+            for configured in sources {
+                for (index, cert_id) in configured.cert_idents.iter().enumerate() {
+                    let Some(cert) = store.cert_store.get(cert_id) else {
+                        continue;
+                    };
+
+                    // Only the client certificates are a chain
+                    let role = match configured.source {
+                        CertificateSource::ClientCert => {
+                            CertificateRole::in_chain(cert, index).as_str()
+                        }
+                        _ => "",
+                    };
+                    let serial_number = cert.serial_number().to_string();
+                    let subject = cert.subject_common_name().unwrap_or_default();
+                    let issuer = cert.issuer_common_name().unwrap_or_default();
+                    let index = index.to_string();
+                    set_validity(
+                        &self.configured_not_before,
+                        &self.configured_not_after,
+                        &[
+                            target.as_str(),
+                            configured.source.as_str(),
+                            index.as_str(),
+                            role,
+                            serial_number.as_str(),
+                            subject.as_str(),
+                            issuer.as_str(),
+                        ],
+                        cert,
+                    );
+                }
+            }
+        }
+    }
+
+    fn update_leaf(
+        &self,
+        ep_state: &EndpointState,
+        leaf: &ParsedCertificate,
+        details: &LeafDetails,
+    ) {
+        let target = ep_state
+            .target
+            .as_ref()
+            .map(|target| target.to_string())
+            .unwrap_or_default();
+        let endpoint = ep_state.endpoint.to_string();
+        let server_name = ep_state.endpoint.server_name.to_str();
+        let serial_number = leaf.serial_number().to_string();
+
+        match self.hostname_match.get_metric_with_label_values(&[
+            target.as_str(),
+            endpoint.as_str(),
+            server_name.as_ref(),
+        ]) {
+            Ok(metric) => metric.set(details.hostname_match.into()),
+            Err(e) => {
+                error!("Failed to get metric: {}", e);
+            }
+        }
+
+        let leaf_labels = [
+            target.as_str(),
+            endpoint.as_str(),
+            server_name.as_ref(),
+            serial_number.as_str(),
+        ];
+        if let Some(ct_compliant) = ep_state.ct_compliant {
+            match self.ct_compliant.get_metric_with_label_values(&leaf_labels) {
+                Ok(metric) => metric.set(ct_compliant.into()),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+        if let Some(scts) = &details.scts {
+            match self.sct_count.get_metric_with_label_values(&leaf_labels) {
+                Ok(metric) => metric.set(scts.len() as i64),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+            for sct in scts {
+                let log_id = sct.log_id_base64();
+                match self.sct_timestamp.get_metric_with_label_values(&[
+                    target.as_str(),
+                    endpoint.as_str(),
+                    server_name.as_ref(),
+                    serial_number.as_str(),
+                    log_id.as_str(),
+                ]) {
+                    Ok(metric) => metric.set((sct.timestamp / 1000) as i64),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+        }
+
+        for san in details.sans.iter().flatten() {
+            let value = san.to_string();
+            match self.sans.get_metric_with_label_values(&[
+                target.as_str(),
+                endpoint.as_str(),
+                server_name.as_ref(),
+                serial_number.as_str(),
+                san.kind(),
+                value.as_str(),
+            ]) {
+                Ok(metric) => metric.set(1),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn set_validity(
+    not_before: &IntGaugeVec,
+    not_after: &IntGaugeVec,
+    label_values: &[&str],
+    cert: &ParsedCertificate,
+) {
+    match not_before.get_metric_with_label_values(label_values) {
+        Ok(metric) => metric.set(cert.not_before()),
+        Err(e) => {
+            error!("Failed to get metric: {}", e);
+        }
+    }
+    match not_after.get_metric_with_label_values(label_values) {
+        Ok(metric) => metric.set(cert.not_after()),
+        Err(e) => {
+            error!("Failed to get metric: {}", e);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ProbeMetrics {
+    pub success: IntGaugeVec,
+    pub duration: GaugeVec,
+    pub error: IntGaugeVec,
+    pub chain_verified: IntGaugeVec,
+    pub chain_length: IntGaugeVec,
+    pub connection_info: IntGaugeVec,
+    pub supported: IntGaugeVec,
+    pub ocsp_stapled: IntGaugeVec,
+    pub ocsp_status: IntGaugeVec,
+    pub ocsp_this_update: IntGaugeVec,
+    pub ocsp_next_update: IntGaugeVec,
+    pub ocsp_signature_valid: IntGaugeVec,
+}
+
+impl ProbeMetrics {
+    fn new(config: &MetricsConfig) -> AnyResult<Self> {
+        let endpoint_labels = ["target", "endpoint", "server_name"];
+        let probe_subsystem = config.family_subsystem("probe");
+        let chain_subsystem = config.family_subsystem("chain");
+        let tls_subsystem = config.family_subsystem("tls");
+        let ocsp_subsystem = config.family_subsystem("ocsp");
+
+        let success = IntGaugeVec::new(
+            Opts::new("success", "Whether the probe succeeded")
+                .namespace(config.namespace())
+                .subsystem(probe_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+        let duration = GaugeVec::new(
+            Opts::new(
+                "duration_seconds",
+                "Duration of each phase of the probe in seconds",
+            )
+            .namespace(config.namespace())
+            .subsystem(probe_subsystem.as_str()),
+            &["target", "endpoint", "server_name", "phase"],
+        )?;
+        let error = IntGaugeVec::new(
+            Opts::new(
+                "error",
+                "Whether the probe failed with the class of error, 0 after a successful probe",
+            )
+            .namespace(config.namespace())
+            .subsystem(probe_subsystem.as_str()),
+            &["target", "endpoint", "server_name", "class"],
+        )?;
+        let chain_verified = IntGaugeVec::new(
+            Opts::new(
+                "verified",
+                "Whether the certificate chain passed the verification, with the reason if not",
+            )
+            .namespace(config.namespace())
+            .subsystem(chain_subsystem.as_str()),
+            &["target", "endpoint", "server_name", "reason"],
+        )?;
+        let chain_length = IntGaugeVec::new(
+            Opts::new(
+                "length",
+                "Number of certificates in the chain sent by the server",
+            )
+            .namespace(config.namespace())
+            .subsystem(chain_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+
+        // There is no label for the key exchange group, since rustls 0.22 doesn't expose it
+        let connection_info = IntGaugeVec::new(
+            Opts::new(
+                "connection_info",
+                "Parameters negotiated in the TLS handshake, always 1",
+            )
+            .namespace(config.namespace())
+            .subsystem(tls_subsystem.as_str()),
+            &[
+                "target",
+                "endpoint",
+                "server_name",
+                "version",
+                "cipher_suite",
+                "alpn_protocol",
+            ],
+        )?;
+
+        let supported = IntGaugeVec::new(
+            Opts::new(
+                "supported",
+                "Whether the server accepts the protocol version and cipher suite",
+            )
+            .namespace(config.namespace())
+            .subsystem(tls_subsystem.as_str()),
+            &["target", "endpoint", "server_name", "version", "cipher"],
+        )?;
+
+        let ocsp_stapled = IntGaugeVec::new(
+            Opts::new(
+                "stapled",
+                "Whether the server stapled an OCSP response in the handshake",
+            )
+            .namespace(config.namespace())
+            .subsystem(ocsp_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+        let ocsp_status = IntGaugeVec::new(
+            Opts::new(
+                "status",
+                "The status of the leaf certificate in the stapled OCSP response",
+            )
+            .namespace(config.namespace())
+            .subsystem(ocsp_subsystem.as_str()),
+            &["target", "endpoint", "server_name", "status"],
+        )?;
+        let ocsp_this_update = IntGaugeVec::new(
+            Opts::new(
+                "this_update",
+                "The stapled OCSP response this update timestamp",
+            )
+            .namespace(config.namespace())
+            .subsystem(ocsp_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+        let ocsp_next_update = IntGaugeVec::new(
+            Opts::new(
+                "next_update",
+                "The stapled OCSP response next update timestamp",
+            )
+            .namespace(config.namespace())
+            .subsystem(ocsp_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+        let ocsp_signature_valid = IntGaugeVec::new(
+            Opts::new(
+                "signature_valid",
+                "Whether the stapled OCSP response is signed by the issuer of the leaf certificate",
+            )
+            .namespace(config.namespace())
+            .subsystem(ocsp_subsystem.as_str()),
+            &endpoint_labels,
+        )?;
+
+        Ok(Self {
+            success,
+            duration,
+            error,
+            chain_verified,
+            chain_length,
+            connection_info,
+            supported,
+            ocsp_stapled,
+            ocsp_status,
+            ocsp_this_update,
+            ocsp_next_update,
+            ocsp_signature_valid,
+        })
+    }
+
+    fn update(&self, store: &Store) {
+        for ep_state in store.endpoint_store.values() {
+            let target = ep_state
+                .target
+                .as_ref()
+                .map(|target| target.to_string())
+                .unwrap_or_default();
+            let endpoint = ep_state.endpoint.to_string();
+            let server_name = ep_state.endpoint.server_name.to_str();
+            let labels = [target.as_str(), endpoint.as_str(), server_name.as_ref()];
+
+            self.set_result(&labels, &ep_state.probe_result);
+
+            if let Some(connection) = &ep_state.connection {
+                match self.connection_info.get_metric_with_label_values(&[
+                    labels[0],
+                    labels[1],
+                    labels[2],
+                    connection.version.as_str(),
+                    connection.cipher_suite.as_str(),
+                    connection.alpn_protocol.as_deref().unwrap_or_default(),
+                ]) {
+                    Ok(metric) => metric.set(1),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+
+            for scan_result in ep_state.scan.iter().flatten() {
+                match self.supported.get_metric_with_label_values(&[
+                    labels[0],
+                    labels[1],
+                    labels[2],
+                    scan_result.version.as_str(),
+                    scan_result.cipher_suite.as_str(),
+                ]) {
+                    Ok(metric) => metric.set(scan_result.supported.into()),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+
+            if !ep_state.cert_idents.is_empty() {
+                match self.chain_length.get_metric_with_label_values(&labels) {
+                    Ok(metric) => metric.set(ep_state.cert_idents.len() as i64),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+                // The response is stapled along with the certificates
+                match self.ocsp_stapled.get_metric_with_label_values(&labels) {
+                    Ok(metric) => metric.set(ep_state.ocsp_stapled.into()),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+
+            if let Some(ocsp) = &ep_state.ocsp {
+                self.set_ocsp(&labels, ocsp);
+            }
+
+            if let Some(verification) = &ep_state.verification {
+                let reason = verification.err().map_or("", |reason| reason.as_str());
+                match self
+                    .chain_verified
+                    .get_metric_with_label_values(&[labels[0], labels[1], labels[2], reason])
+                {
+                    Ok(metric) => metric.set(verification.is_ok().into()),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+
+            let phases = [
+                ("resolve", ep_state.duration.resolve),
+                ("connect", ep_state.duration.connect),
+                ("handshake", ep_state.duration.handshake),
+            ];
+            for (phase, duration) in phases {
+                match self
+                    .duration
+                    .get_metric_with_label_values(&[labels[0], labels[1], labels[2], phase])
+                {
+                    Ok(metric) => metric.set(duration.as_secs_f64()),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+            }
+        }
+
+        for (target, probe_error) in &store.target_errors {
+            let target = target.to_string();
+            self.set_result(&[target.as_str(), "", ""], &Err(probe_error.clone()));
+        }
+    }
+
+    fn set_ocsp(&self, labels: &[&str; 3], ocsp: &OcspResponse) {
+        for status in OcspCertStatus::ALL {
+            match self.ocsp_status.get_metric_with_label_values(&[
+                labels[0],
+                labels[1],
+                labels[2],
+                status.as_str(),
+            ]) {
+                Ok(metric) => metric.set((ocsp.status == status).into()),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+
+        match self.ocsp_this_update.get_metric_with_label_values(labels) {
+            Ok(metric) => metric.set(ocsp.this_update),
+            Err(e) => {
+                error!("Failed to get metric: {}", e);
+            }
+        }
+        if let Some(next_update) = ocsp.next_update {
+            match self.ocsp_next_update.get_metric_with_label_values(labels) {
+                Ok(metric) => metric.set(next_update),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+        if let Some(signature_valid) = ocsp.signature_valid {
+            match self
+                .ocsp_signature_valid
+                .get_metric_with_label_values(labels)
+            {
+                Ok(metric) => metric.set(signature_valid.into()),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+    }
+
+    fn set_result(&self, labels: &[&str; 3], result: &Result<(), ProbeError>) {
+        match self.success.get_metric_with_label_values(labels) {
+            Ok(metric) => metric.set(result.is_ok().into()),
+            Err(e) => {
+                error!("Failed to get metric: {}", e);
+            }
+        }
+        // Every class is exported, so that the error of the previous probe is reset to 0
+        let current = result.as_ref().err().map(|probe_error| probe_error.class);
+        for class in ErrorClass::ALL {
+            match self.error.get_metric_with_label_values(&[
+                labels[0],
+                labels[1],
+                labels[2],
+                class.as_str(),
+            ]) {
+                Ok(metric) => metric.set((current == Some(class)).into()),
+                Err(e) => {
+                    error!("Failed to get metric: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CrlMetrics {
+    pub this_update: IntGaugeVec,
+    pub next_update: IntGaugeVec,
+}
+
+impl CrlMetrics {
+    fn new(config: &MetricsConfig) -> AnyResult<Self> {
+        let crl_subsystem = config.family_subsystem("crl");
+        let this_update = IntGaugeVec::new(
+            Opts::new(
+                "this_update",
+                "Certificate revocation list this update timestamp",
+            )
+            .namespace(config.namespace())
+            .subsystem(crl_subsystem.as_str()),
+            &["target", "file", "issuer"],
+        )?;
+        let next_update = IntGaugeVec::new(
+            Opts::new(
+                "next_update",
+                "Certificate revocation list next update timestamp",
+            )
+            .namespace(config.namespace())
+            .subsystem(crl_subsystem.as_str()),
+            &["target", "file", "issuer"],
+        )?;
+
+        Ok(Self {
+            this_update,
+            next_update,
+        })
+    }
+
+    /// Export the lists of the configuration, the global ones with an empty `target`
+    fn update(&self, store: &Store) {
+        for (target, crls) in &store.configured_crls {
+            let target = target
+                .as_ref()
+                .map(|target| target.to_string())
+                .unwrap_or_default();
+            // The same list may be configured twice
+            let mut exported = HashSet::new();
+            for crl in crls {
+                let issuer = crl.issuer_common_name().unwrap_or_default();
+                if !exported.insert((crl.file.as_str(), issuer.clone())) {
+                    continue;
+                }
+                let labels = [target.as_str(), crl.file.as_str(), issuer.as_str()];
+                match self.this_update.get_metric_with_label_values(&labels) {
+                    Ok(metric) => metric.set(crl.this_update),
+                    Err(e) => {
+                        error!("Failed to get metric: {}", e);
+                    }
+                }
+                if let Some(next_update) = crl.next_update {
+                    match self.next_update.get_metric_with_label_values(&labels) {
+                        Ok(metric) => metric.set(next_update),
+                        Err(e) => {
+                            error!("Failed to get metric: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        configs::{CompatibilityPreset, ConnectionParameters},
+        testing::{endpoint, label, parsed_certificate, probe_result, series},
+    };
+    use rustls_pki_types::CertificateDer;
+
+    fn resolve_error() -> ProbeError {
+        ProbeError {
+            class: ErrorClass::Resolve,
+            message: "no record found".to_owned(),
+        }
+    }
+
+    #[test]
+    fn collect_target_errors() {
+        let mut store = Store::default();
+        store.update_probe_error(&"example.com:443".parse().unwrap(), resolve_error());
+
+        let collector = StoreCollector::from_store(&store, &MetricsConfig::default()).unwrap();
+        let metric_families = collector.collect();
+
+        assert_eq!(series(&metric_families, "tlsce_probe_success").len(), 1);
+        assert_eq!(
+            series(&metric_families, "tlsce_probe_error").len(),
+            ErrorClass::ALL.len()
+        );
+        assert!(series(&metric_families, "tlsce_cert_not_after").is_empty());
+        assert!(series(&metric_families, "tlsce_probe_duration_seconds").is_empty());
+    }
+
+    #[test]
+    fn configured_subsystem() {
+        let mut store = Store::default();
+        store.update_probe_error(&"example.com:443".parse().unwrap(), resolve_error());
+        let names = |config: &MetricsConfig| -> Vec<String> {
+            let collector = StoreCollector::from_store(&store, config).unwrap();
+            collector
+                .collect()
+                .iter()
+                .map(|family| family.get_name().to_owned())
+                .collect()
+        };
+
+        let config = MetricsConfig {
+            subsystem: Some("x509".to_owned()),
+            ..Default::default()
+        };
+        let names_x509 = names(&config);
+        assert!(names_x509.contains(&"tlsce_x509_not_after".to_owned()));
+        assert!(names_x509.contains(&"tlsce_x509_probe_success".to_owned()));
+        assert!(names_x509.contains(&"tlsce_x509_crl_next_update".to_owned()));
+
+        let config = MetricsConfig {
+            compatibility: Some(CompatibilityPreset::SslExporter),
+            ..Default::default()
+        };
+        let names_ssl = names(&config);
+        assert!(names_ssl.contains(&"ssl_cert_not_after".to_owned()));
+        assert!(names_ssl.contains(&"ssl_probe_success".to_owned()));
+    }
+
+    #[test]
+    fn collect_file_certificates() {
+        let mut store = Store::default();
+        store.update_file_source(
+            &"/etc/ssl/*.pem".parse().unwrap(),
+            vec![(
+                "/etc/ssl/leaf.der".into(),
+                parsed_certificate().encode_der().unwrap(),
+            )],
+            None,
+        );
+
+        let collector = StoreCollector::from_store(&store, &MetricsConfig::default()).unwrap();
+        let metric_families = collector.collect();
+
+        assert!(series(&metric_families, "tlsce_cert_not_after").is_empty());
+        let metrics = series(&metric_families, "tlsce_file_cert_not_after");
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(label(&metrics[0], "file"), "/etc/ssl/leaf.der");
+    }
+
+    #[test]
+    fn collect_configured_certificates() {
+        let mut store = Store::default();
+        let conn_params = ConnectionParameters {
+            ca_certs: vec![CertificateDer::from(
+                parsed_certificate().encode_der().unwrap(),
+            )],
+            ..Default::default()
+        };
+        store
+            .update_configured_certificates(None, &conn_params)
+            .unwrap();
+
+        let collector = StoreCollector::from_store(&store, &MetricsConfig::default()).unwrap();
+        let metric_families = collector.collect();
+
+        assert!(series(&metric_families, "tlsce_cert_not_after").is_empty());
+        let metrics = series(&metric_families, "tlsce_configured_cert_not_after");
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(label(&metrics[0], "source"), "global_anchor");
+    }
+
+    #[test]
+    fn reset_probe_error() {
+        let target = "example.com:443".parse().unwrap();
+        let errors = |store: &Store| -> Vec<(String, i64)> {
+            let collector = StoreCollector::from_store(store, &MetricsConfig::default()).unwrap();
+            let mut errors: Vec<(String, i64)> = collector
+                .probe_metrics
+                .error
+                .collect()
+                .iter()
+                .flat_map(|family| family.get_metric())
+                .filter(|metric| metric.get_gauge().get_value() != 0.0)
+                .map(|metric| {
+                    (
+                        label(metric, "class").to_owned(),
+                        metric.get_gauge().get_value() as i64,
+                    )
+                })
+                .collect();
+            errors.sort();
+            errors
+        };
+
+        let mut store = Store::default();
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    Vec::new(),
+                    Err(ProbeError {
+                        class: ErrorClass::Timeout,
+                        message: "deadline has elapsed".to_owned(),
+                    }),
+                )],
+            )
+            .unwrap();
+        assert_eq!(errors(&store), [("timeout".to_owned(), 1)]);
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(endpoint("192.0.2.1:443"), Vec::new(), Ok(()))],
+            )
+            .unwrap();
+        assert!(errors(&store).is_empty());
+    }
+
+    #[test]
+    fn fail_endpoints_of_target_error() {
+        let target = "example.com:443".parse().unwrap();
+        let mut store = Store::default();
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    vec![parsed_certificate()],
+                    Ok(()),
+                )],
+            )
+            .unwrap();
+        store.update_probe_error(&target, resolve_error());
+
+        let collector = StoreCollector::from_store(&store, &MetricsConfig::default()).unwrap();
+        let metric_families = collector.collect();
+        let success = series(&metric_families, "tlsce_probe_success");
+        // Both the endpoint and the target itself
+        assert_eq!(success.len(), 2);
+        assert!(success
+            .iter()
+            .all(|metric| metric.get_gauge().get_value() == 0.0));
+    }
+
+    #[test]
+    fn collect_chain_position() {
+        let mut store = Store::default();
+        store
+            .update_probe_result(
+                &"example.com:443".parse().unwrap(),
+                // The certificates built for testing are all self-signed
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    vec![parsed_certificate(), parsed_certificate()],
+                    Ok(()),
+                )],
+            )
+            .unwrap();
+
+        let collector = StoreCollector::from_store(&store, &MetricsConfig::default()).unwrap();
+        let metric_families = collector.collect();
+
+        let mut positions: Vec<(&str, &str)> = series(&metric_families, "tlsce_cert_not_after")
+            .iter()
+            .map(|metric| (label(metric, "chain_index"), label(metric, "role")))
+            .collect();
+        positions.sort();
+        assert_eq!(positions, [("0", "leaf"), ("1", "root-in-chain")]);
+        assert_eq!(
+            series(&metric_families, "tlsce_chain_length")[0]
+                .get_gauge()
+                .get_value(),
+            2.0
+        );
+    }
+}
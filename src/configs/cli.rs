@@ -0,0 +1,34 @@
+use super::GlobalConfig;
+use clap::Parser;
+use std::net::IpAddr;
+
+/// Command line options, which take precedence over the configuration files
+#[derive(Clone, Debug, Parser)]
+#[command(author, version, about)]
+pub struct CliArgs {
+    /// Address to listen on for the web interface. Can be repeated.
+    #[arg(long = "web.listen-address", value_name = "ADDRESS")]
+    pub listen_addresses: Vec<IpAddr>,
+
+    /// Port to listen on for the web interface
+    #[arg(long = "web.port", value_name = "PORT")]
+    pub port: Option<u16>,
+
+    /// Path under which to expose metrics
+    #[arg(long = "web.metrics-path", value_name = "PATH")]
+    pub metrics_path: Option<String>,
+}
+
+impl CliArgs {
+    pub fn apply(&self, config: &mut GlobalConfig) {
+        if !self.listen_addresses.is_empty() {
+            config.web.listen_addresses = self.listen_addresses.clone();
+        }
+        if let Some(port) = self.port {
+            config.web.port = port;
+        }
+        if let Some(metrics_path) = &self.metrics_path {
+            config.web.metrics_path = metrics_path.clone();
+        }
+    }
+}
@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::io::Result as IoResult;
-use std::{convert::Infallible, ffi::OsString, path::PathBuf, str::FromStr};
+use std::{
+    convert::Infallible,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::{fs::File, io::AsyncReadExt};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -13,13 +18,55 @@ pub enum FileContent {
     Path {
         path: PathBuf,
     },
+    /// A PKCS#12, JKS or JCEKS keystore
+    Keystore {
+        keystore: PathBuf,
+        /// Only use the entry with this alias
+        #[serde(default)]
+        alias: Option<String>,
+        #[serde(default, flatten)]
+        password: KeystorePassword,
+    },
+}
+
+/// The password of the keystores, given inline or read from a file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KeystorePassword {
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+}
+
+impl KeystorePassword {
+    /// Return the configured password, without the line break at the end of the file.
+    pub async fn load(&self) -> IoResult<Option<String>> {
+        if let Some(password) = &self.password {
+            return Ok(Some(password.clone()));
+        }
+        match &self.password_file {
+            Some(path) => {
+                let password = tokio::fs::read_to_string(path).await?;
+                Ok(Some(password.trim_end_matches(['\r', '\n']).to_owned()))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 impl FileContent {
+    /// The path of the file, or `None` if the content is inline
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FileContent::Inline { .. } => None,
+            FileContent::Path { path } | FileContent::Keystore { keystore: path, .. } => Some(path),
+        }
+    }
+
     pub async fn load_file(self) -> IoResult<Vec<u8>> {
         match self {
             FileContent::Inline { content } => Ok(content),
-            FileContent::Path { path } => {
+            FileContent::Path { path } | FileContent::Keystore { keystore: path, .. } => {
                 let mut file = File::open(path).await?;
                 let mut buffer = Vec::new();
                 file.read_to_end(&mut buffer).await?;
@@ -1,18 +1,39 @@
-use anyhow::{Context, Result as AnyResult};
+use crate::starttls::StartTls;
+use anyhow::Result as AnyResult;
 use config::{Config, Environment as ConfigEnv, File as ConfigFile};
 use duration_str::{deserialize_duration, deserialize_option_duration};
 use serde::{Deserialize, Serialize};
-use std::{default::Default, ops::Add, time::Duration};
+use std::{
+    collections::HashMap,
+    default::Default,
+    net::{IpAddr, Ipv4Addr},
+    ops::Add,
+    time::Duration,
+};
 
+mod cli;
 mod file_content;
 mod parameters;
 mod private_key;
 
-pub use file_content::FileContent;
+pub use cli::CliArgs;
+pub use file_content::{FileContent, KeystorePassword};
 pub use parameters::*;
 
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
 pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(600);
+pub const DEFAULT_STALE_TTL: Duration = Duration::from_secs(86400);
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_PORT: u16 = 9880;
+pub const DEFAULT_METRICS_PATH: &str = "/metrics";
+pub const DEFAULT_NAMESPACE: &str = "tlsce";
+pub const DEFAULT_SUBSYSTEM: &str = "cert";
+pub const DEFAULT_CT_MIN_LOGS: usize = 2;
+
+/// The configuration files to load, without their extension. Later files override earlier ones.
+pub const CONFIG_FILE_NAMES: &[&str] = &["/etc/tls-certificate-exporter", "config"];
+/// The extensions which the configuration files may have
+pub const CONFIG_FILE_EXTENSIONS: &[&str] = &["yaml", "yml", "toml", "json"];
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GlobalConfig {
@@ -24,18 +45,50 @@ pub struct GlobalConfig {
     #[serde(default)]
     pub scheduler: SchedulerConfig,
 
+    #[serde(default)]
+    pub store: StoreConfig,
+
+    #[serde(default)]
+    pub web: WebConfig,
+
+    /// How long to wait for the in-flight probes and requests on shutdown
+    #[serde(
+        default = "default_shutdown_timeout",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub shutdown_timeout: Duration,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
     #[serde(default)]
     pub targets: Vec<TargetConfig>,
 
+    /// Certificates read from local files, e.g. the ones which are not served on a port
+    #[serde(default)]
+    pub files: Vec<FileSourceConfig>,
+
     #[serde(default)]
     pub trusted_anchors: Vec<FileContent>,
+
+    /// Certificate revocation lists checked for every target
+    #[serde(default)]
+    pub crls: Vec<FileContent>,
+
+    #[serde(default)]
+    pub ct: CtConfig,
+
+    #[serde(default)]
+    pub modules: HashMap<String, ModuleConfig>,
 }
 
 impl GlobalConfig {
     pub fn load_config() -> AnyResult<Self> {
-        let cfg = Config::builder()
-            .add_source(ConfigFile::with_name("/etc/tls-certificate-exporter/").required(false))
-            .add_source(ConfigFile::with_name("config").required(false))
+        let mut builder = Config::builder();
+        for name in CONFIG_FILE_NAMES {
+            builder = builder.add_source(ConfigFile::with_name(name).required(false));
+        }
+        let cfg = builder
             .add_source(ConfigEnv::with_prefix("TLSCE").separator("."))
             .build()?
             .try_deserialize()?;
@@ -49,8 +102,16 @@ impl Default for GlobalConfig {
             workers: Default::default(),
             default_timeout: default_timeout(),
             scheduler: Default::default(),
+            store: Default::default(),
+            web: Default::default(),
+            shutdown_timeout: default_shutdown_timeout(),
+            metrics: Default::default(),
             targets: Default::default(),
+            files: Default::default(),
             trusted_anchors: Default::default(),
+            crls: Default::default(),
+            ct: Default::default(),
+            modules: Default::default(),
         }
     }
 }
@@ -63,6 +124,30 @@ const fn default_interval() -> Duration {
     DEFAULT_INTERVAL
 }
 
+const fn default_stale_ttl() -> Duration {
+    DEFAULT_STALE_TTL
+}
+
+const fn default_shutdown_timeout() -> Duration {
+    DEFAULT_SHUTDOWN_TIMEOUT
+}
+
+const fn default_ct_min_logs() -> usize {
+    DEFAULT_CT_MIN_LOGS
+}
+
+fn default_listen_addresses() -> Vec<IpAddr> {
+    vec![Ipv4Addr::LOCALHOST.into()]
+}
+
+const fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_metrics_path() -> String {
+    DEFAULT_METRICS_PATH.to_owned()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TargetConfig {
     pub target: String,
@@ -74,6 +159,28 @@ pub struct TargetConfig {
     pub tls_config: TargetTlsConfig,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileSourceConfig {
+    /// A file, a directory, or a glob pattern matching them. PEM files may contain several
+    /// certificates, keystores are detected by their content, while other files are read as a
+    /// single DER certificate.
+    pub path: String,
+    /// The password of the keystores matched by the path
+    #[serde(default, flatten)]
+    pub password: KeystorePassword,
+    #[serde(default, flatten)]
+    pub schedule_config: SchedulerOverrideConfig,
+}
+
+/// Connection settings which can be selected by name from the `/probe` endpoint
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ModuleConfig {
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub tls_config: TargetTlsConfig,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TargetTlsConfig {
     #[serde(default)]
@@ -82,10 +189,24 @@ pub struct TargetTlsConfig {
     pub cert: Option<FileContent>,
     #[serde(default)]
     pub key: Option<FileContent>,
+    /// Certificate revocation lists checked in addition to the global ones
+    #[serde(default)]
+    pub crls: Vec<FileContent>,
     #[serde(default)]
     pub server_name: Option<String>,
+    /// Probe each endpoint once for every name, e.g. for virtual hosts sharing an address
+    #[serde(default)]
+    pub server_names: Vec<String>,
     #[serde(default)]
     pub insecure_skip_verify: bool,
+    #[serde(default)]
+    pub starttls: Option<StartTls>,
+    /// The protocols offered with ALPN, e.g. `h2` and `http/1.1`
+    #[serde(default)]
+    pub alpn_protocols: Vec<String>,
+    /// Enumerate the protocol versions and cipher suites accepted by the server
+    #[serde(default)]
+    pub scan: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -105,6 +226,119 @@ impl Default for SchedulerConfig {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoreConfig {
+    /// Drop the endpoints which have been unreachable for this long
+    #[serde(
+        default = "default_stale_ttl",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub stale_ttl: Duration,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            stale_ttl: default_stale_ttl(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CtConfig {
+    /// The Certificate Transparency logs in the v3 JSON format of Google.
+    /// The policy is only checked if it's set.
+    #[serde(default)]
+    pub log_list: Option<FileContent>,
+    /// The number of distinct logs which must have signed the embedded SCTs
+    #[serde(default = "default_ct_min_logs")]
+    pub min_logs: usize,
+}
+
+impl Default for CtConfig {
+    fn default() -> Self {
+        Self {
+            log_list: None,
+            min_logs: default_ct_min_logs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebConfig {
+    #[serde(default = "default_listen_addresses")]
+    pub listen_addresses: Vec<IpAddr>,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_metrics_path")]
+    pub metrics_path: String,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            listen_addresses: default_listen_addresses(),
+            port: default_port(),
+            metrics_path: default_metrics_path(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub subsystem: Option<String>,
+    /// Use the prefix and the label names of another exporter where they have the same meaning
+    #[serde(default)]
+    pub compatibility: Option<CompatibilityPreset>,
+}
+
+impl MetricsConfig {
+    pub fn namespace(&self) -> &str {
+        match (&self.namespace, self.compatibility) {
+            (Some(namespace), _) => namespace,
+            (None, Some(CompatibilityPreset::SslExporter)) => "ssl",
+            (None, None) => DEFAULT_NAMESPACE,
+        }
+    }
+
+    pub fn subsystem(&self) -> &str {
+        match (&self.subsystem, self.compatibility) {
+            (Some(subsystem), _) => subsystem,
+            (None, Some(CompatibilityPreset::SslExporter)) => "cert",
+            (None, None) => DEFAULT_SUBSYSTEM,
+        }
+    }
+
+    /// The subsystem of the families which are not about a certificate, e.g. `probe`, which
+    /// follows the subsystem when one is configured
+    pub fn family_subsystem(&self, family: &str) -> String {
+        match &self.subsystem {
+            Some(subsystem) => format!("{}_{}", subsystem, family),
+            None => family.to_owned(),
+        }
+    }
+
+    /// Map the name of a label to the one used by the compatibility preset
+    pub fn label_name<'a>(&self, label: &'a str) -> &'a str {
+        match (self.compatibility, label) {
+            (Some(CompatibilityPreset::SslExporter), "serial_number") => "serial_no",
+            (Some(CompatibilityPreset::SslExporter), "subject") => "cn",
+            (Some(CompatibilityPreset::SslExporter), "issuer") => "issuer_cn",
+            (_, label) => label,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatibilityPreset {
+    /// [ssl_exporter](https://github.com/ribbybibby/ssl_exporter)
+    SslExporter,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct SchedulerOverrideConfig {
     #[serde(default, deserialize_with = "deserialize_option_duration")]
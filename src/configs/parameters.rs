@@ -1,11 +1,21 @@
 use super::private_key::PrivateKey;
-use super::{FileContent, GlobalConfig, TargetConfig};
-use crate::{certificate_interceptor::CertificateInterceptor, error::ErrorReason};
+use super::{FileContent, GlobalConfig, ModuleConfig, TargetConfig, TargetTlsConfig};
+use crate::{
+    cert::{CertificateRevocationList, CtLogList, CtPolicy},
+    certificate_interceptor::CertificateInterceptor,
+    error::ErrorReason,
+    keystore::Keystore,
+    starttls::StartTls,
+};
 use anyhow::Result as AnyResult;
 use futures::{future::OptionFuture, prelude::*, stream::FuturesUnordered};
-use rustls_pki_types::{CertificateDer, TrustAnchor};
+use rustls_pki_types::{CertificateDer, ServerName};
 use std::{io::Cursor, sync::Arc, time::Duration};
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::rustls::{
+    crypto::{ring::default_provider, CryptoProvider},
+    ClientConfig, RootCertStore, SupportedProtocolVersion, DEFAULT_VERSIONS,
+};
+use x509_certificate::X509Certificate;
 
 #[derive(Clone, Debug)]
 pub struct ConnectionParameters {
@@ -13,13 +23,30 @@ pub struct ConnectionParameters {
 
     pub trusted_anchors: RootCertStore,
 
+    /// The configured trust anchors, without the system ones, to monitor their validity.
+    /// Not merged with the global ones.
+    pub ca_certs: Vec<CertificateDer<'static>>,
+
+    pub crls: Vec<Arc<CertificateRevocationList>>,
+
+    /// Only configured globally
+    pub ct_policy: Option<Arc<CtPolicy>>,
+
     pub certs: Vec<CertificateDer<'static>>,
 
     pub key: Option<PrivateKey>,
 
-    pub server_name: Option<String>,
+    /// Override the name derived from the target host for SNI and verification.
+    /// Each endpoint is probed once per name.
+    pub server_names: Vec<ServerName<'static>>,
 
     pub insecure_skip_verify: bool,
+
+    pub starttls: Option<StartTls>,
+
+    pub alpn_protocols: Vec<Vec<u8>>,
+
+    pub scan: bool,
 }
 
 impl Default for ConnectionParameters {
@@ -27,29 +54,47 @@ impl Default for ConnectionParameters {
         Self {
             timeout: None,
             trusted_anchors: RootCertStore::empty(),
+            ca_certs: Vec::new(),
+            crls: Vec::new(),
+            ct_policy: None,
             certs: Vec::new(),
             key: None,
-            server_name: None,
+            server_names: Vec::new(),
             insecure_skip_verify: false,
+            starttls: None,
+            alpn_protocols: Vec::new(),
+            scan: false,
         }
     }
 }
 
 impl ConnectionParameters {
     pub fn build_tls_config(&self) -> AnyResult<(ClientConfig, Arc<CertificateInterceptor>)> {
-        let builder = ClientConfig::builder();
+        self.build_tls_config_with(Arc::new(default_provider()), DEFAULT_VERSIONS)
+    }
+
+    /// Build the configuration with only the given cipher suites and protocol versions,
+    /// e.g. to find out which ones the server accepts.
+    pub fn build_tls_config_with(
+        &self,
+        provider: Arc<CryptoProvider>,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> AnyResult<(ClientConfig, Arc<CertificateInterceptor>)> {
+        let builder =
+            ClientConfig::builder_with_provider(provider).with_protocol_versions(versions)?;
 
         let root_certs = Arc::new(self.trusted_anchors.clone());
 
         let interceptor = Arc::new(CertificateInterceptor::new(
             root_certs,
+            self.crls.iter().map(|crl| crl.der().clone()),
             self.insecure_skip_verify,
         ));
 
         let builder = builder
             .dangerous()
             .with_custom_certificate_verifier(interceptor.clone());
-        let config = if !self.certs.is_empty() {
+        let mut config = if !self.certs.is_empty() {
             if let Some(key) = self.key.as_ref() {
                 builder.with_client_auth_cert(self.certs.clone(), key.clone_key())?
             } else {
@@ -58,6 +103,7 @@ impl ConnectionParameters {
         } else {
             builder.with_no_client_auth()
         };
+        config.alpn_protocols = self.alpn_protocols.clone();
 
         Ok((config, interceptor))
     }
@@ -71,6 +117,10 @@ impl ConnectionParameters {
         if p.timeout.is_none() {
             p.timeout = default_params.timeout;
         }
+        p.crls.extend(default_params.crls.iter().cloned());
+        if p.ct_policy.is_none() {
+            p.ct_policy = default_params.ct_policy.clone();
+        }
 
         p
     }
@@ -104,13 +154,41 @@ impl ConnectionParameters {
         let trusted_anchors = tasks.try_concat().await?;
 
         let mut root_store = RootCertStore::empty();
-        for cert in trusted_anchors {
-            root_store.add(cert)?;
+        for cert in &trusted_anchors {
+            root_store.add(cert.clone())?;
         }
 
+        let crls = config
+            .crls
+            .clone()
+            .into_iter()
+            .map(|file| async { load_crls(file).await })
+            .collect::<FuturesUnordered<_>>()
+            .try_concat()
+            .await?;
+
+        let ct_policy = OptionFuture::from(
+            config
+                .ct
+                .log_list
+                .clone()
+                .map(|file| async { load_ct_log_list(file).await }),
+        )
+        .await
+        .transpose()?
+        .map(|log_list| {
+            Arc::new(CtPolicy {
+                log_list,
+                min_logs: config.ct.min_logs,
+            })
+        });
+
         let mut default_parameters = ConnectionParameters {
             timeout: Some(config.default_timeout),
             trusted_anchors: root_store,
+            ca_certs: trusted_anchors,
+            crls,
+            ct_policy,
             ..Default::default()
         };
         if let Err(e) = default_parameters.load_system_roots() {
@@ -121,9 +199,19 @@ impl ConnectionParameters {
     }
 
     pub async fn load_from_target_config(target_config: &TargetConfig) -> AnyResult<Self> {
+        Self::load_from_tls_config(target_config.timeout, &target_config.tls_config).await
+    }
+
+    pub async fn load_from_module_config(module_config: &ModuleConfig) -> AnyResult<Self> {
+        Self::load_from_tls_config(module_config.timeout, &module_config.tls_config).await
+    }
+
+    async fn load_from_tls_config(
+        timeout: Option<Duration>,
+        tls_config: &TargetTlsConfig,
+    ) -> AnyResult<Self> {
         let trusted_anchors = OptionFuture::from(
-            target_config
-                .tls_config
+            tls_config
                 .ca
                 .clone()
                 .map(|file| async { load_certificates(file).await }),
@@ -133,50 +221,179 @@ impl ConnectionParameters {
         .unwrap_or_default();
 
         let mut root_store = RootCertStore::empty();
-        for cert in trusted_anchors {
-            root_store.add(cert)?;
+        for cert in &trusted_anchors {
+            root_store.add(cert.clone())?;
         }
 
-        let certs = OptionFuture::from(
-            target_config
-                .tls_config
+        let crls = tls_config
+            .crls
+            .clone()
+            .into_iter()
+            .map(|file| async { load_crls(file).await })
+            .collect::<FuturesUnordered<_>>()
+            .try_concat()
+            .await?;
+
+        let (certs, keystore_key) = OptionFuture::from(
+            tls_config
                 .cert
                 .clone()
-                .map(|file| async { load_certificates(file).await }),
+                .map(|file| async { load_client_certificate(file).await }),
         )
         .await
         .transpose()?
         .unwrap_or_default();
 
+        // The key of a keystore can be overridden by a separate one
         let key = OptionFuture::from(
-            target_config
-                .tls_config
+            tls_config
                 .key
                 .clone()
                 .map(|file| async { load_private_key(file).await }),
         )
         .await
-        .transpose()?;
+        .transpose()?
+        .or(keystore_key);
+        if let (Some(cert), Some(key)) = (certs.first(), key.as_ref()) {
+            check_client_key(cert, key);
+        }
+
+        let server_names = tls_config
+            .server_name
+            .iter()
+            .chain(tls_config.server_names.iter())
+            .map(|name| {
+                ServerName::try_from(name.as_str())
+                    .map(|name| name.to_owned())
+                    .map_err(|_| ErrorReason::InvalidServerName)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Self {
-            timeout: target_config.timeout,
+            timeout,
             trusted_anchors: root_store,
+            ca_certs: trusted_anchors,
+            crls,
+            ct_policy: None,
             certs,
             key,
-            server_name: target_config.tls_config.server_name.clone(),
-            insecure_skip_verify: target_config.tls_config.insecure_skip_verify,
+            server_names,
+            insecure_skip_verify: tls_config.insecure_skip_verify,
+            starttls: tls_config.starttls,
+            alpn_protocols: tls_config
+                .alpn_protocols
+                .iter()
+                .map(|protocol| protocol.as_bytes().to_vec())
+                .collect(),
+            scan: tls_config.scan,
         })
     }
 }
 
+/// Read the keystore of the file, or `None` if it isn't one.
+async fn load_keystore(file: &FileContent) -> AnyResult<Option<Keystore>> {
+    let FileContent::Keystore {
+        keystore: path,
+        alias,
+        password,
+    } = file
+    else {
+        return Ok(None);
+    };
+
+    let data = tokio::fs::read(path).await?;
+    let password = password.load().await?;
+    let mut keystore = Keystore::parse(&data, password.as_deref())?;
+    if let Some(alias) = alias {
+        keystore.select(alias);
+    }
+    Ok(Some(keystore))
+}
+
+/// Load the certificates of every entry of a keystore, e.g. as the trust anchors, or the ones in
+/// a PEM file.
 async fn load_certificates(file: FileContent) -> AnyResult<Vec<CertificateDer<'static>>> {
+    if let Some(keystore) = load_keystore(&file).await? {
+        return Ok(keystore
+            .entries
+            .into_iter()
+            .flat_map(|entry| entry.certificates)
+            .collect());
+    }
+
     let data = file.load_file().await?;
     let mut buf = Cursor::new(data);
     let pems = rustls_pemfile::certs(&mut buf).collect::<Result<Vec<_>, std::io::Error>>()?;
     Ok(pems)
 }
 
+/// Warn if the client certificate doesn't belong to the private key, which would only fail
+/// during the handshake.
+fn check_client_key(cert: &CertificateDer<'_>, key: &PrivateKey) {
+    let subject = || {
+        X509Certificate::from_der(cert)
+            .ok()
+            .and_then(|cert| cert.subject_common_name())
+            .unwrap_or_default()
+    };
+    match key.matches_certificate(cert) {
+        Ok(Some(true)) | Ok(None) => {}
+        Ok(Some(false)) => warn!(
+            "The client certificate {} doesn't match its private key",
+            subject()
+        ),
+        Err(e) => warn!(
+            "Failed to check the client certificate {} against its private key: {}",
+            subject(),
+            e
+        ),
+    }
+}
+
+async fn load_crls(file: FileContent) -> AnyResult<Vec<Arc<CertificateRevocationList>>> {
+    let path = file
+        .path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let data = file.load_file().await?;
+    let mut buf = Cursor::new(data);
+    rustls_pemfile::crls(&mut buf)
+        .map(|crl| {
+            let mut crl = CertificateRevocationList::from_der(crl?)?;
+            crl.file = path.clone();
+            Ok(Arc::new(crl))
+        })
+        .collect()
+}
+
+async fn load_ct_log_list(file: FileContent) -> AnyResult<CtLogList> {
+    let data = file.load_file().await?;
+    CtLogList::from_json(&data)
+}
+
+/// Load the client certificate chain, along with its key if it is read from a keystore.
+async fn load_client_certificate(
+    file: FileContent,
+) -> AnyResult<(Vec<CertificateDer<'static>>, Option<PrivateKey>)> {
+    if let Some(keystore) = load_keystore(&file).await? {
+        let entry = keystore
+            .into_key_entry()
+            .ok_or(ErrorReason::MissingPrivateKey)?;
+        return Ok((entry.certificates, entry.key.map(PrivateKey::from)));
+    }
+
+    Ok((load_certificates(file).await?, None))
+}
+
 async fn load_private_key(file: FileContent) -> AnyResult<PrivateKey> {
+    if let Some(keystore) = load_keystore(&file).await? {
+        let key = keystore
+            .into_key_entry()
+            .and_then(|entry| entry.key)
+            .ok_or(ErrorReason::MissingPrivateKey)?;
+        return Ok(key.into());
+    }
+
     let data = file.load_file().await?;
     PrivateKey::load_from_pem(&data)
 }
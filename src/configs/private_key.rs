@@ -1,10 +1,20 @@
 use crate::error::ErrorReason;
 use anyhow::Result as AnyResult;
-use rustls_pki_types::PrivateKeyDer;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, SignatureVerificationAlgorithm};
 use std::{
     io::Cursor,
     ops::{Deref, DerefMut},
 };
+use tokio_rustls::rustls::{crypto::ring::sign::any_supported_type, SignatureScheme};
+use webpki::EndEntityCert;
+
+/// The schemes used to check whether a key matches a certificate
+const CHECK_SCHEMES: [SignatureScheme; 4] = [
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
 
 /// Private key with Clone
 #[derive(Debug)]
@@ -16,6 +26,30 @@ impl PrivateKey {
         let key = rustls_pemfile::private_key(&mut buf)?.ok_or(ErrorReason::MissingPrivateKey)?;
         Ok(Self(key))
     }
+
+    /// Whether the key belongs to the certificate, by verifying a signature made with the key
+    /// with the public key of the certificate. `None` if the key type isn't supported.
+    pub fn matches_certificate(&self, cert: &CertificateDer<'_>) -> AnyResult<Option<bool>> {
+        const MESSAGE: &[u8] = b"tls-certificate-exporter";
+
+        let Some(signer) = any_supported_type(&self.0)?.choose_scheme(&CHECK_SCHEMES) else {
+            return Ok(None);
+        };
+        let algorithm: &dyn SignatureVerificationAlgorithm = match signer.scheme() {
+            SignatureScheme::ED25519 => webpki::ring::ED25519,
+            SignatureScheme::ECDSA_NISTP256_SHA256 => webpki::ring::ECDSA_P256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384 => webpki::ring::ECDSA_P384_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256 => webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+            _ => return Ok(None),
+        };
+        let signature = signer.sign(MESSAGE)?;
+
+        let cert = EndEntityCert::try_from(cert)?;
+        Ok(Some(
+            cert.verify_signature(algorithm, MESSAGE, &signature)
+                .is_ok(),
+        ))
+    }
 }
 
 impl Deref for PrivateKey {
@@ -49,3 +83,37 @@ impl Clone for PrivateKey {
         Self(self.0.clone_key())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+    use rustls_pki_types::PrivatePkcs8KeyDer;
+    use x509_certificate::{InMemorySigningKeyPair, X509CertificateBuilder};
+
+    fn private_key() -> PrivateKey {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                .unwrap();
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec())).into()
+    }
+
+    #[test]
+    fn match_certificate() {
+        let key = private_key();
+        let key_pair = InMemorySigningKeyPair::from_pkcs8_der(key.secret_der()).unwrap();
+        let cert = X509CertificateBuilder::default()
+            .create_with_key_pair(&key_pair)
+            .unwrap();
+        let cert = CertificateDer::from(cert.encode_der().unwrap());
+
+        assert_eq!(key.matches_certificate(&cert).unwrap(), Some(true));
+        assert_eq!(
+            private_key().matches_certificate(&cert).unwrap(),
+            Some(false)
+        );
+    }
+}
@@ -1,7 +1,11 @@
 #[cfg(feature = "backtrace")]
 use backtrace::Backtrace;
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+};
 use thiserror::Error;
+use tokio_rustls::rustls::{CertificateError, Error as RustlsError};
 
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -61,10 +65,368 @@ pub enum ErrorReason {
     TlsError(#[from] tokio_rustls::rustls::Error),
     #[error("Invalid endpoint")]
     InvalidEndpoint,
+    #[error("Invalid server name")]
+    InvalidServerName,
+    #[error("Invalid file pattern")]
+    InvalidFilePattern(#[from] glob::PatternError),
+    #[error("Invalid metrics path")]
+    InvalidMetricsPath,
     #[error("Invalid PEM tag")]
     InvalidPemTag,
     #[error("Missing private key")]
     MissingPrivateKey,
+    #[error("STARTTLS is not supported by the server")]
+    StartTlsUnsupported,
+    #[error("STARTTLS negotiation error: {0}")]
+    StartTlsNegotiation(String),
+    #[error("Invalid OCSP response: {0}")]
+    OcspResponse(String),
+    #[error("Invalid SCT: {0}")]
+    InvalidSct(String),
+    #[error("Invalid keystore: {0}")]
+    InvalidKeystore(String),
+    #[error("The component is not running")]
+    ComponentStopped,
     #[error("Unknown error")]
     Unknown,
 }
+
+/// A stable classification of probe failures, which is exported as a metric label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    Resolve,
+    Timeout,
+    Refused,
+    StartTls,
+    HandshakeFailure,
+    Untrusted,
+    Expired,
+    NameMismatch,
+    Unknown,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 9] = [
+        ErrorClass::Resolve,
+        ErrorClass::Timeout,
+        ErrorClass::Refused,
+        ErrorClass::StartTls,
+        ErrorClass::HandshakeFailure,
+        ErrorClass::Untrusted,
+        ErrorClass::Expired,
+        ErrorClass::NameMismatch,
+        ErrorClass::Unknown,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Resolve => "resolve",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Refused => "refused",
+            ErrorClass::StartTls => "starttls",
+            ErrorClass::HandshakeFailure => "handshake_failure",
+            ErrorClass::Untrusted => "untrusted",
+            ErrorClass::Expired => "expired",
+            ErrorClass::NameMismatch => "name_mismatch",
+            ErrorClass::Unknown => "unknown",
+        }
+    }
+
+    /// Classify by the first error in the chain which we know about
+    pub fn classify(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(Self::classify_error)
+            .unwrap_or(ErrorClass::Unknown)
+    }
+
+    fn classify_error(err: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(reason) = err.downcast_ref::<ErrorReason>() {
+            match reason {
+                ErrorReason::Resolver(_) => Some(ErrorClass::Resolve),
+                ErrorReason::StartTlsUnsupported | ErrorReason::StartTlsNegotiation(_) => {
+                    Some(ErrorClass::StartTls)
+                }
+                ErrorReason::Io(e) => Self::classify_io_error(e),
+                ErrorReason::TlsError(e) => Some(Self::classify_rustls_error(e)),
+                _ => None,
+            }
+        } else if let Some(e) = err.downcast_ref::<IoError>() {
+            Self::classify_io_error(e)
+        } else if let Some(e) = err.downcast_ref::<RustlsError>() {
+            Some(Self::classify_rustls_error(e))
+        } else if err.is::<tokio::time::error::Elapsed>() {
+            Some(ErrorClass::Timeout)
+        } else if err.is::<hickory_resolver::error::ResolveError>() {
+            Some(ErrorClass::Resolve)
+        } else {
+            None
+        }
+    }
+
+    fn classify_io_error(err: &IoError) -> Option<Self> {
+        // TLS errors are wrapped into `std::io::Error` by tokio-rustls
+        if let Some(class) = err.get_ref().and_then(|e| Self::classify_error(e)) {
+            return Some(class);
+        }
+
+        match err.kind() {
+            IoErrorKind::TimedOut => Some(ErrorClass::Timeout),
+            IoErrorKind::ConnectionRefused => Some(ErrorClass::Refused),
+            IoErrorKind::ConnectionReset | IoErrorKind::UnexpectedEof => {
+                Some(ErrorClass::HandshakeFailure)
+            }
+            _ => None,
+        }
+    }
+
+    fn classify_rustls_error(err: &RustlsError) -> Self {
+        match err {
+            RustlsError::InvalidCertificate(e) => Self::classify_certificate_error(e),
+            _ => ErrorClass::HandshakeFailure,
+        }
+    }
+
+    pub fn classify_certificate_error(err: &CertificateError) -> Self {
+        match err {
+            CertificateError::Expired | CertificateError::NotValidYet => ErrorClass::Expired,
+            CertificateError::NotValidForName => ErrorClass::NameMismatch,
+            _ => ErrorClass::Untrusted,
+        }
+    }
+}
+
+impl Display for ErrorClass {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Why the certificate chain failed the WebPKI verification, which is exported as a metric label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VerifyReason {
+    Expired,
+    NotValidYet,
+    UnknownIssuer,
+    BadSignature,
+    NameMismatch,
+    Revoked,
+    UnknownRevocationStatus,
+    BadEncoding,
+    InvalidPurpose,
+    UnhandledCriticalExtension,
+    InvalidCaUsage,
+    NameConstraintViolation,
+    PathLimitExceeded,
+    Other,
+}
+
+impl VerifyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerifyReason::Expired => "expired",
+            VerifyReason::NotValidYet => "not_yet_valid",
+            VerifyReason::UnknownIssuer => "unknown_issuer",
+            VerifyReason::BadSignature => "bad_signature",
+            VerifyReason::NameMismatch => "name_mismatch",
+            VerifyReason::Revoked => "revoked",
+            VerifyReason::UnknownRevocationStatus => "unknown_revocation_status",
+            VerifyReason::BadEncoding => "bad_encoding",
+            VerifyReason::InvalidPurpose => "invalid_purpose",
+            VerifyReason::UnhandledCriticalExtension => "unhandled_critical_extension",
+            VerifyReason::InvalidCaUsage => "invalid_ca_usage",
+            VerifyReason::NameConstraintViolation => "name_constraint_violation",
+            VerifyReason::PathLimitExceeded => "path_limit_exceeded",
+            VerifyReason::Other => "other",
+        }
+    }
+
+    pub fn from_rustls_error(err: &RustlsError) -> Self {
+        match err {
+            RustlsError::InvalidCertificate(e) => Self::from_certificate_error(e),
+            _ => VerifyReason::Other,
+        }
+    }
+
+    pub fn from_certificate_error(err: &CertificateError) -> Self {
+        match err {
+            CertificateError::Expired => VerifyReason::Expired,
+            CertificateError::NotValidYet => VerifyReason::NotValidYet,
+            CertificateError::UnknownIssuer => VerifyReason::UnknownIssuer,
+            CertificateError::BadSignature => VerifyReason::BadSignature,
+            CertificateError::NotValidForName => VerifyReason::NameMismatch,
+            CertificateError::Revoked => VerifyReason::Revoked,
+            CertificateError::UnknownRevocationStatus => VerifyReason::UnknownRevocationStatus,
+            CertificateError::BadEncoding => VerifyReason::BadEncoding,
+            CertificateError::InvalidPurpose => VerifyReason::InvalidPurpose,
+            CertificateError::UnhandledCriticalExtension => {
+                VerifyReason::UnhandledCriticalExtension
+            }
+            // The verifier of rustls only maps the common errors of webpki
+            CertificateError::Other(other) => other
+                .0
+                .downcast_ref::<webpki::Error>()
+                .map_or(VerifyReason::Other, Self::from_webpki_error),
+            _ => VerifyReason::Other,
+        }
+    }
+
+    pub fn from_webpki_error(err: &webpki::Error) -> Self {
+        use webpki::Error::*;
+        match err {
+            CertExpired | InvalidCertValidity => VerifyReason::Expired,
+            CertNotValidYet => VerifyReason::NotValidYet,
+            UnknownIssuer => VerifyReason::UnknownIssuer,
+            InvalidSignatureForPublicKey
+            | UnsupportedSignatureAlgorithm
+            | UnsupportedSignatureAlgorithmForPublicKey => VerifyReason::BadSignature,
+            CertNotValidForName => VerifyReason::NameMismatch,
+            CertRevoked => VerifyReason::Revoked,
+            UnknownRevocationStatus => VerifyReason::UnknownRevocationStatus,
+            BadDer | BadDerTime | TrailingData(_) => VerifyReason::BadEncoding,
+            RequiredEkuNotFound => VerifyReason::InvalidPurpose,
+            UnsupportedCriticalExtension => VerifyReason::UnhandledCriticalExtension,
+            CaUsedAsEndEntity | EndEntityUsedAsCa => VerifyReason::InvalidCaUsage,
+            NameConstraintViolation => VerifyReason::NameConstraintViolation,
+            MaximumPathDepthExceeded
+            | MaximumPathBuildCallsExceeded
+            | MaximumSignatureChecksExceeded
+            | MaximumNameConstraintComparisonsExceeded => VerifyReason::PathLimitExceeded,
+            _ => VerifyReason::Other,
+        }
+    }
+}
+
+impl Display for VerifyReason {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use std::{future::pending, sync::Arc, time::Duration};
+    use tokio::time::timeout;
+    use tokio_rustls::rustls::OtherError;
+
+    /// Like tokio-rustls reports the TLS errors
+    fn tls_io_error(err: RustlsError) -> anyhow::Error {
+        anyhow::Error::new(IoError::new(IoErrorKind::InvalidData, err)).context("handshake failed")
+    }
+
+    #[test]
+    fn classify_resolve() {
+        let err = hickory_resolver::error::ResolveError::from("no record found");
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err)),
+            ErrorClass::Resolve
+        );
+    }
+
+    #[tokio::test]
+    async fn classify_timeout() {
+        let elapsed = timeout(Duration::ZERO, pending::<()>()).await.unwrap_err();
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(elapsed).context("probe timed out")),
+            ErrorClass::Timeout
+        );
+        let err = IoError::from(IoErrorKind::TimedOut);
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err)),
+            ErrorClass::Timeout
+        );
+    }
+
+    #[test]
+    fn classify_refused() {
+        let err = IoError::from(IoErrorKind::ConnectionRefused);
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err).context("failed to connect")),
+            ErrorClass::Refused
+        );
+    }
+
+    #[test]
+    fn classify_starttls() {
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(ErrorReason::StartTlsUnsupported)),
+            ErrorClass::StartTls
+        );
+        let err = ErrorReason::StartTlsNegotiation("502 not implemented".to_owned());
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err)),
+            ErrorClass::StartTls
+        );
+    }
+
+    #[test]
+    fn classify_handshake_failure() {
+        let err =
+            RustlsError::AlertReceived(tokio_rustls::rustls::AlertDescription::HandshakeFailure);
+        assert_eq!(
+            ErrorClass::classify(&tls_io_error(err)),
+            ErrorClass::HandshakeFailure
+        );
+        let err = IoError::from(IoErrorKind::UnexpectedEof);
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err)),
+            ErrorClass::HandshakeFailure
+        );
+    }
+
+    #[test]
+    fn classify_untrusted() {
+        let err = RustlsError::InvalidCertificate(CertificateError::UnknownIssuer);
+        assert_eq!(
+            ErrorClass::classify(&tls_io_error(err)),
+            ErrorClass::Untrusted
+        );
+    }
+
+    #[test]
+    fn classify_expired() {
+        let err = RustlsError::InvalidCertificate(CertificateError::Expired);
+        assert_eq!(
+            ErrorClass::classify(&tls_io_error(err)),
+            ErrorClass::Expired
+        );
+    }
+
+    #[test]
+    fn classify_name_mismatch() {
+        let err = RustlsError::InvalidCertificate(CertificateError::NotValidForName);
+        assert_eq!(
+            ErrorClass::classify(&tls_io_error(err)),
+            ErrorClass::NameMismatch
+        );
+    }
+
+    #[test]
+    fn classify_unknown() {
+        assert_eq!(
+            ErrorClass::classify(&anyhow!("something went wrong")),
+            ErrorClass::Unknown
+        );
+        let err = IoError::from(IoErrorKind::PermissionDenied);
+        assert_eq!(
+            ErrorClass::classify(&anyhow::Error::new(err)),
+            ErrorClass::Unknown
+        );
+    }
+
+    #[test]
+    fn verify_reason_of_webpki_error() {
+        // webpki errors which rustls doesn't map are wrapped
+        let err = CertificateError::Other(OtherError(Arc::new(webpki::Error::CaUsedAsEndEntity)));
+        assert_eq!(
+            VerifyReason::from_certificate_error(&err),
+            VerifyReason::InvalidCaUsage
+        );
+        let err = CertificateError::Other(OtherError(Arc::new(IoError::from(IoErrorKind::Other))));
+        assert_eq!(
+            VerifyReason::from_certificate_error(&err),
+            VerifyReason::Other
+        );
+    }
+}
@@ -0,0 +1,35 @@
+#!/bin/sh
+# Generate the keystores used by the tests, with OpenSSL 3 and the keytool of JDK 17.
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj /CN=fixture-ca \
+    -days 36500 -keyout "$tmp/ca.key" -out "$tmp/ca.pem"
+openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj /CN=leaf.test \
+    -keyout "$tmp/leaf.key" -out "$tmp/leaf.csr"
+openssl x509 -req -in "$tmp/leaf.csr" -CA "$tmp/ca.pem" -CAkey "$tmp/ca.key" -CAcreateserial \
+    -days 36500 -out "$tmp/leaf.pem"
+
+# PBES2 with AES-256-CBC, and the 3DES and RC2 schemes of PKCS#12
+openssl pkcs12 -export -in "$tmp/leaf.pem" -inkey "$tmp/leaf.key" -certfile "$tmp/ca.pem" \
+    -name leaf -passout pass:secret -out pbes2.p12
+openssl pkcs12 -export -legacy -in "$tmp/leaf.pem" -inkey "$tmp/leaf.key" -certfile "$tmp/ca.pem" \
+    -name leaf -passout pass:secret -out legacy.p12
+openssl pkcs12 -export -nokeys -in "$tmp/ca.pem" -passout pass: -out nopass.p12
+
+for type in JKS JCEKS; do
+    file=keystore.$(echo $type | tr A-Z a-z)
+    rm -f "$file"
+    keytool -importkeystore -srckeystore pbes2.p12 -srcstoretype PKCS12 -srcstorepass secret \
+        -destkeystore "$file" -deststoretype $type -deststorepass changeit -destkeypass changeit
+    keytool -importcert -noprompt -alias ca -file "$tmp/ca.pem" -storepass changeit \
+        -keystore "$file" -storetype $type
+done
+keytool -genseckey -alias aes -keyalg AES -keysize 128 -storepass changeit -keypass changeit \
+    -keystore keystore.jceks -storetype JCEKS
+
+rm -f keytool.p12
+keytool -importkeystore -srckeystore keystore.jks -srcstoretype JKS -srcstorepass changeit \
+    -destkeystore keytool.p12 -deststoretype PKCS12 -deststorepass changeit
@@ -0,0 +1,663 @@
+//! The keystore formats of Java, which are documented by the source of
+//! `sun.security.provider.JavaKeyStore` and `com.sun.crypto.provider.JceKeyStore`.
+
+use super::{pkcs12, KeystoreEntry, JCEKS_MAGIC};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use bcder::{ConstOid, Oid};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use md5::{Digest, Md5};
+use ring::digest::{digest, Context, SHA1_FOR_LEGACY_USE_ONLY};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+/// 1.3.6.1.4.1.42.2.17.1.1, the proprietary key protection of JKS
+const OID_JKS_KEY_PROTECTOR: ConstOid = Oid(&[43, 6, 1, 4, 1, 42, 2, 17, 1, 1]);
+/// 1.3.6.1.4.1.42.2.19.1, PBEWithMD5AndTripleDES used by JCEKS
+const OID_PBE_MD5_TRIPLE_DES: ConstOid = Oid(&[43, 6, 1, 4, 1, 42, 2, 19, 1]);
+
+const TAG_PRIVATE_KEY: u32 = 1;
+const TAG_TRUSTED_CERT: u32 = 2;
+const TAG_SECRET_KEY: u32 = 3;
+
+const SHA1_LEN: usize = 20;
+
+fn invalid(reason: &str) -> ErrorReason {
+    ErrorReason::InvalidKeystore(format!("JKS: {}", reason))
+}
+
+pub fn parse(data: &[u8], password: Option<&str>) -> AnyResult<Vec<KeystoreEntry>> {
+    let jceks = data.starts_with(&JCEKS_MAGIC);
+    let mut reader = Reader(data);
+    let (version, count) = reader
+        .take(4)
+        .and_then(|_magic| Some((reader.u32()?, reader.u32()?)))
+        .ok_or_else(|| invalid("truncated header"))?;
+    if version != 1 && version != 2 {
+        return Err(invalid(&format!("unsupported version {}", version)).into());
+    }
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let tag = reader.u32().ok_or_else(|| invalid("truncated entry"))?;
+        let alias = reader.utf().ok_or_else(|| invalid("truncated alias"))?;
+        // The creation date in milliseconds
+        reader.u64().ok_or_else(|| invalid("truncated entry"))?;
+
+        match tag {
+            TAG_PRIVATE_KEY => {
+                let encrypted = reader
+                    .u32_prefixed()
+                    .ok_or_else(|| invalid("truncated private key"))?;
+                let chain_len = reader.u32().ok_or_else(|| invalid("truncated chain"))?;
+                let mut certificates = Vec::new();
+                for _ in 0..chain_len {
+                    if let Some(cert) = reader.certificate(version)? {
+                        certificates.push(cert);
+                    }
+                }
+                let key = match password.map(|password| decrypt_key(encrypted, password)) {
+                    Some(Ok(key)) => Some(key),
+                    Some(Err(e)) => {
+                        warn!("Failed to decrypt the private key {}: {}", alias, e);
+                        None
+                    }
+                    None => None,
+                };
+                entries.push(KeystoreEntry {
+                    alias,
+                    certificates,
+                    key,
+                });
+            }
+            TAG_TRUSTED_CERT => {
+                if let Some(cert) = reader.certificate(version)? {
+                    entries.push(KeystoreEntry {
+                        alias,
+                        certificates: vec![cert],
+                        key: None,
+                    });
+                }
+            }
+            TAG_SECRET_KEY if jceks => {
+                // A serialized `javax.crypto.SealedObject`, which has nothing to monitor
+                let mut skipper = JavaObjectSkipper::new(reader);
+                skipper
+                    .skip_stream()
+                    .ok_or_else(|| invalid("unsupported secret key entry"))?;
+                reader = skipper.reader;
+            }
+            _ => return Err(invalid(&format!("unknown entry type {}", tag)).into()),
+        }
+    }
+
+    if let Some(password) = password {
+        let signed = &data[..data.len() - reader.0.len()];
+        let checksum = reader
+            .take(SHA1_LEN)
+            .ok_or_else(|| invalid("missing checksum"))?;
+        let mut context = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&utf16_be(password));
+        context.update(b"Mighty Aphrodite");
+        context.update(signed);
+        if context.finish().as_ref() != checksum {
+            return Err(invalid("the password is incorrect or the keystore is corrupted").into());
+        }
+    }
+
+    Ok(entries)
+}
+
+fn decrypt_key(encrypted: &[u8], password: &str) -> AnyResult<PrivateKeyDer<'static>> {
+    let (algorithm, params, data) = pkcs12::decode_encrypted_private_key_info(encrypted)?;
+    let key = if algorithm == OID_JKS_KEY_PROTECTOR {
+        decrypt_jks_key(&data, password)?
+    } else if algorithm == OID_PBE_MD5_TRIPLE_DES {
+        let (salt, iterations) = pkcs12::decode_pbe_params(params.as_slice())?;
+        decrypt_jceks_key(&data, password, &salt, iterations)?
+    } else {
+        return Err(invalid(&format!("unsupported key protection {}", algorithm)).into());
+    };
+    Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)))
+}
+
+/// XOR with a SHA-1 keystream, which is followed by a SHA-1 of the plaintext
+fn decrypt_jks_key(data: &[u8], password: &str) -> AnyResult<Vec<u8>> {
+    if data.len() < SHA1_LEN * 2 {
+        return Err(invalid("truncated private key").into());
+    }
+    let password = utf16_be(password);
+    let (salt, rest) = data.split_at(SHA1_LEN);
+    let (encrypted, check) = rest.split_at(rest.len() - SHA1_LEN);
+
+    let mut block = salt.to_vec();
+    let mut key = Vec::with_capacity(encrypted.len());
+    for chunk in encrypted.chunks(SHA1_LEN) {
+        block = digest(
+            &SHA1_FOR_LEGACY_USE_ONLY,
+            &[password.as_slice(), &block].concat(),
+        )
+        .as_ref()
+        .to_vec();
+        key.extend(chunk.iter().zip(&block).map(|(c, k)| c ^ k));
+    }
+
+    let expected = digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        &[password.as_slice(), &key].concat(),
+    );
+    if expected.as_ref() != check {
+        return Err(invalid("the key password is incorrect").into());
+    }
+    Ok(key)
+}
+
+/// PBEWithMD5AndTripleDES of the SunJCE provider
+fn decrypt_jceks_key(
+    data: &[u8],
+    password: &str,
+    salt: &[u8],
+    iterations: u64,
+) -> AnyResult<Vec<u8>> {
+    if salt.len() != 8 {
+        return Err(invalid("invalid salt").into());
+    }
+    // The password is converted to ASCII by `PBEKey`
+    let password: Vec<u8> = password.chars().map(|c| (c as u32 & 0x7F) as u8).collect();
+    let salt = jceks_salt(salt);
+
+    let mut key_iv = Vec::with_capacity(32);
+    for half in salt.chunks(4) {
+        let mut hashed = half.to_vec();
+        for _ in 0..iterations {
+            hashed = Md5::new()
+                .chain_update(&hashed)
+                .chain_update(&password)
+                .finalize()
+                .to_vec();
+        }
+        key_iv.extend(hashed);
+    }
+
+    let mut buf = data.to_vec();
+    let len = cbc::Decryptor::<des::TdesEde3>::new_from_slices(&key_iv[..24], &key_iv[24..])
+        .map_err(|_| invalid("invalid key length"))?
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .map_err(|_| invalid("the key password is incorrect"))?
+        .len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// If the two halves of the salt are the same, `PBES1Core` means to invert the first one, but
+/// assigns `salt[3 - 1]` instead of `salt[3 - i]`, which has to be replicated to get the same key.
+fn jceks_salt(salt: &[u8]) -> Vec<u8> {
+    let mut salt = salt.to_vec();
+    if salt[..4] == salt[4..] {
+        for i in 0..2 {
+            let tmp = salt[i];
+            salt[i] = salt[3 - i];
+            salt[2] = tmp;
+        }
+    }
+    salt
+}
+
+fn utf16_be(password: &str) -> Vec<u8> {
+    password.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    /// A string written by `DataOutput.writeUTF`
+    fn utf(&mut self) -> Option<String> {
+        let len = self.u16()?;
+        // Modified UTF-8 only differs from UTF-8 in NUL and supplementary characters
+        Some(String::from_utf8_lossy(self.take(len as usize)?).into_owned())
+    }
+
+    fn u32_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()?;
+        self.take(len as usize)
+    }
+
+    /// A certificate, or `None` if it isn't an X.509 one
+    fn certificate(&mut self, version: u32) -> AnyResult<Option<CertificateDer<'static>>> {
+        let cert_type = if version == 2 {
+            self.utf().ok_or_else(|| invalid("truncated certificate"))?
+        } else {
+            "X.509".to_owned()
+        };
+        let der = self
+            .u32_prefixed()
+            .ok_or_else(|| invalid("truncated certificate"))?;
+        Ok((cert_type == "X.509").then(|| CertificateDer::from(der.to_vec())))
+    }
+}
+
+const STREAM_MAGIC: u16 = 0xACED;
+const BASE_WIRE_HANDLE: u32 = 0x7E0000;
+
+const TC_NULL: u8 = 0x70;
+const TC_REFERENCE: u8 = 0x71;
+const TC_CLASSDESC: u8 = 0x72;
+const TC_OBJECT: u8 = 0x73;
+const TC_STRING: u8 = 0x74;
+const TC_ARRAY: u8 = 0x75;
+const TC_CLASS: u8 = 0x76;
+const TC_BLOCKDATA: u8 = 0x77;
+const TC_ENDBLOCKDATA: u8 = 0x78;
+const TC_BLOCKDATALONG: u8 = 0x7A;
+const TC_LONGSTRING: u8 = 0x7C;
+const TC_PROXYCLASSDESC: u8 = 0x7D;
+const TC_ENUM: u8 = 0x7E;
+
+const SC_WRITE_METHOD: u8 = 0x01;
+const SC_SERIALIZABLE: u8 = 0x02;
+const SC_EXTERNALIZABLE: u8 = 0x04;
+const SC_BLOCK_DATA: u8 = 0x08;
+
+/// The deepest nesting of objects and class descriptions, which bounds the recursion on a
+/// crafted stream
+const MAX_DEPTH: usize = 64;
+
+/// The layout of a serialized class, which is needed to skip the fields of its objects
+#[derive(Clone, Default)]
+struct ClassDesc {
+    /// e.g. `[B` for the arrays of bytes
+    name: String,
+    flags: u8,
+    /// The type codes of the fields, e.g. `I` for `int` or `L` for an object
+    fields: Vec<u8>,
+    super_class: Option<usize>,
+}
+
+/// Skip a Java serialization stream, following the grammar of the Object Serialization
+/// Stream Protocol. `None` means the stream is truncated or not supported.
+struct JavaObjectSkipper<'a> {
+    reader: Reader<'a>,
+    /// The class descriptions by handle, and `None` for the other objects
+    handles: Vec<Option<ClassDesc>>,
+    depth: usize,
+}
+
+impl<'a> JavaObjectSkipper<'a> {
+    fn new(reader: Reader<'a>) -> Self {
+        Self {
+            reader,
+            handles: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Run a step which may recurse, or fail past [`MAX_DEPTH`].
+    fn nested<T>(&mut self, step: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let result = step(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn skip_stream(&mut self) -> Option<()> {
+        if self.reader.u16()? != STREAM_MAGIC {
+            return None;
+        }
+        // version
+        self.reader.u16()?;
+        self.skip_content()
+    }
+
+    fn new_handle(&mut self) -> usize {
+        self.handles.push(None);
+        self.handles.len() - 1
+    }
+
+    fn reference(&mut self) -> Option<usize> {
+        let handle = self.reader.u32()?.checked_sub(BASE_WIRE_HANDLE)? as usize;
+        (handle < self.handles.len()).then_some(handle)
+    }
+
+    fn skip_content(&mut self) -> Option<()> {
+        let tc = self.reader.u8()?;
+        self.skip_content_with(tc)
+    }
+
+    fn skip_content_with(&mut self, tc: u8) -> Option<()> {
+        self.nested(|this| this.skip_tagged(tc))
+    }
+
+    fn skip_tagged(&mut self, tc: u8) -> Option<()> {
+        match tc {
+            TC_NULL => {}
+            TC_REFERENCE => {
+                self.reference()?;
+            }
+            TC_STRING => {
+                let len = self.reader.u16()?;
+                self.reader.take(len as usize)?;
+                self.new_handle();
+            }
+            TC_LONGSTRING => {
+                let len = usize::try_from(self.reader.u64()?).ok()?;
+                self.reader.take(len)?;
+                self.new_handle();
+            }
+            TC_CLASSDESC | TC_PROXYCLASSDESC => {
+                self.class_desc_with(tc)?;
+            }
+            TC_CLASS => {
+                self.class_desc()?;
+                self.new_handle();
+            }
+            TC_ENUM => {
+                self.class_desc()?;
+                self.new_handle();
+                // The name of the constant
+                self.skip_content()?;
+            }
+            TC_BLOCKDATA => {
+                let len = self.reader.u8()?;
+                self.reader.take(len as usize)?;
+            }
+            TC_BLOCKDATALONG => {
+                let len = self.reader.u32()?;
+                self.reader.take(len as usize)?;
+            }
+            TC_ARRAY => self.skip_array()?,
+            TC_OBJECT => self.skip_object()?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Read a class description, which may be null or a reference to a previous one.
+    fn class_desc(&mut self) -> Option<Option<usize>> {
+        match self.reader.u8()? {
+            TC_NULL => Some(None),
+            TC_REFERENCE => self.reference().map(Some),
+            tc @ (TC_CLASSDESC | TC_PROXYCLASSDESC) => self.class_desc_with(tc).map(Some),
+            _ => None,
+        }
+    }
+
+    fn class_desc_with(&mut self, tc: u8) -> Option<usize> {
+        self.nested(|this| this.new_class_desc(tc))
+    }
+
+    fn new_class_desc(&mut self, tc: u8) -> Option<usize> {
+        let handle = self.new_handle();
+        let mut desc = ClassDesc::default();
+        if tc == TC_CLASSDESC {
+            desc.name = self.reader.utf()?;
+            // serialVersionUID
+            self.reader.u64()?;
+            desc.flags = self.reader.u8()?;
+            for _ in 0..self.reader.u16()? {
+                let type_code = self.reader.u8()?;
+                self.reader.utf()?;
+                if type_code == b'L' || type_code == b'[' {
+                    // The class name of the field
+                    self.skip_content()?;
+                }
+                desc.fields.push(type_code);
+            }
+        } else {
+            // The interface names of the proxy
+            for _ in 0..self.reader.u32()? {
+                self.reader.utf()?;
+            }
+            desc.flags = SC_SERIALIZABLE;
+        }
+        self.skip_annotation()?;
+        desc.super_class = self.class_desc()?;
+        self.handles[handle] = Some(desc);
+        Some(handle)
+    }
+
+    fn skip_annotation(&mut self) -> Option<()> {
+        loop {
+            match self.reader.u8()? {
+                TC_ENDBLOCKDATA => return Some(()),
+                tc => self.skip_content_with(tc)?,
+            }
+        }
+    }
+
+    fn skip_array(&mut self) -> Option<()> {
+        let desc = self.class_desc()?;
+        self.new_handle();
+        // The component type follows the `[` of the class name
+        let type_code = *self.handles[desc?].as_ref()?.name.as_bytes().get(1)?;
+        let len = self.reader.u32()?;
+        for _ in 0..len {
+            self.skip_value(type_code)?;
+        }
+        Some(())
+    }
+
+    fn skip_object(&mut self) -> Option<()> {
+        let mut class = self.class_desc()?;
+        self.new_handle();
+
+        // The fields are written from the topmost superclass
+        let mut hierarchy = Vec::new();
+        while let Some(handle) = class {
+            // A class may reference itself as its superclass
+            if hierarchy.len() == MAX_DEPTH {
+                return None;
+            }
+            let desc = self.handles[handle].clone()?;
+            class = desc.super_class;
+            hierarchy.push(desc);
+        }
+        for desc in hierarchy.into_iter().rev() {
+            if desc.flags & SC_EXTERNALIZABLE != 0 {
+                if desc.flags & SC_BLOCK_DATA == 0 {
+                    return None;
+                }
+                self.skip_annotation()?;
+                continue;
+            }
+            for type_code in desc.fields {
+                self.skip_value(type_code)?;
+            }
+            if desc.flags & SC_WRITE_METHOD != 0 {
+                self.skip_annotation()?;
+            }
+        }
+        Some(())
+    }
+
+    fn skip_value(&mut self, type_code: u8) -> Option<()> {
+        let len = match type_code {
+            b'B' | b'Z' => 1,
+            b'C' | b'S' => 2,
+            b'I' | b'F' => 4,
+            b'J' | b'D' => 8,
+            b'L' | b'[' => return self.skip_content(),
+            _ => return None,
+        };
+        self.reader.take(len).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keystore::JKS_MAGIC;
+
+    fn write_utf(buf: &mut Vec<u8>, value: &str) {
+        buf.extend((value.len() as u16).to_be_bytes());
+        buf.extend(value.as_bytes());
+    }
+
+    fn write_u32_prefixed(buf: &mut Vec<u8>, value: &[u8]) {
+        buf.extend((value.len() as u32).to_be_bytes());
+        buf.extend(value);
+    }
+
+    fn build_jks(password: &str, cert: &[u8]) -> Vec<u8> {
+        let mut buf = JKS_MAGIC.to_vec();
+        buf.extend(2u32.to_be_bytes());
+        buf.extend(1u32.to_be_bytes());
+        buf.extend(TAG_TRUSTED_CERT.to_be_bytes());
+        write_utf(&mut buf, "root");
+        buf.extend(0u64.to_be_bytes());
+        write_utf(&mut buf, "X.509");
+        write_u32_prefixed(&mut buf, cert);
+
+        let mut context = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&utf16_be(password));
+        context.update(b"Mighty Aphrodite");
+        context.update(&buf);
+        buf.extend(context.finish().as_ref());
+        buf
+    }
+
+    #[test]
+    fn parse_trusted_certificate() {
+        let cert = [0x30, 0x03, 0x02, 0x01, 0x01];
+        let jks = build_jks("changeit", &cert);
+
+        let entries = parse(&jks, Some("changeit")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].alias, "root");
+        assert_eq!(entries[0].certificates[0].as_ref(), &cert);
+        assert!(entries[0].key.is_none());
+
+        // The integrity is only checked with a password
+        assert!(parse(&jks, Some("wrong")).is_err());
+        assert_eq!(parse(&jks, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decrypt_jks_protected_key() {
+        let (password, key) = ("secret", b"not really a PKCS#8 key".to_vec());
+        let password_bytes = utf16_be(password);
+        let salt = [7u8; SHA1_LEN];
+
+        let mut block = salt.to_vec();
+        let mut protected = salt.to_vec();
+        for chunk in key.chunks(SHA1_LEN) {
+            block = digest(
+                &SHA1_FOR_LEGACY_USE_ONLY,
+                &[&password_bytes, &block[..]].concat(),
+            )
+            .as_ref()
+            .to_vec();
+            protected.extend(chunk.iter().zip(&block).map(|(c, k)| c ^ k));
+        }
+        protected.extend(
+            digest(
+                &SHA1_FOR_LEGACY_USE_ONLY,
+                &[&password_bytes, &key[..]].concat(),
+            )
+            .as_ref(),
+        );
+
+        assert_eq!(decrypt_jks_key(&protected, password).unwrap(), key);
+        assert!(decrypt_jks_key(&protected, "wrong").is_err());
+    }
+
+    #[test]
+    fn skip_serialized_object() {
+        // An object of `class A implements Serializable { int x; byte[] data; }`
+        let mut stream = vec![0xAC, 0xED, 0x00, 0x05, TC_OBJECT, TC_CLASSDESC];
+        write_utf(&mut stream, "A");
+        stream.extend(1u64.to_be_bytes());
+        stream.push(SC_SERIALIZABLE);
+        stream.extend(2u16.to_be_bytes());
+        stream.push(b'I');
+        write_utf(&mut stream, "x");
+        stream.push(b'[');
+        write_utf(&mut stream, "data");
+        stream.push(TC_STRING);
+        write_utf(&mut stream, "[B");
+        stream.extend([TC_ENDBLOCKDATA, TC_NULL]);
+        // x
+        stream.extend(42u32.to_be_bytes());
+        // data
+        stream.extend([TC_ARRAY, TC_CLASSDESC]);
+        write_utf(&mut stream, "[B");
+        stream.extend(2u64.to_be_bytes());
+        stream.push(SC_SERIALIZABLE);
+        stream.extend(0u16.to_be_bytes());
+        stream.extend([TC_ENDBLOCKDATA, TC_NULL]);
+        write_u32_prefixed(&mut stream, &[1, 2, 3]);
+        stream.extend(b"rest");
+
+        let mut skipper = JavaObjectSkipper::new(Reader(&stream));
+        assert!(skipper.skip_stream().is_some());
+        assert_eq!(skipper.reader.0, b"rest");
+    }
+
+    #[test]
+    fn skip_nested_objects() {
+        // Enum constants whose names are enum constants
+        let nested = |depth| {
+            let mut stream = vec![0xAC, 0xED, 0x00, 0x05];
+            stream.extend([TC_ENUM, TC_NULL].repeat(depth));
+            stream.push(TC_NULL);
+            stream
+        };
+        assert!(JavaObjectSkipper::new(Reader(&nested(10)))
+            .skip_stream()
+            .is_some());
+        assert!(JavaObjectSkipper::new(Reader(&nested(100_000)))
+            .skip_stream()
+            .is_none());
+
+        // A class which is its own superclass
+        let mut stream = vec![0xAC, 0xED, 0x00, 0x05, TC_OBJECT, TC_CLASSDESC];
+        write_utf(&mut stream, "A");
+        stream.extend(1u64.to_be_bytes());
+        stream.push(SC_SERIALIZABLE);
+        stream.extend(0u16.to_be_bytes());
+        stream.extend([TC_ENDBLOCKDATA, TC_REFERENCE]);
+        stream.extend(BASE_WIRE_HANDLE.to_be_bytes());
+        assert!(JavaObjectSkipper::new(Reader(&stream))
+            .skip_stream()
+            .is_none());
+    }
+
+    #[test]
+    fn swap_equal_salt_halves() {
+        assert_eq!(
+            jceks_salt(&[1, 2, 3, 4, 1, 2, 3, 4]),
+            [4, 1, 2, 4, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            jceks_salt(&[1, 2, 3, 4, 5, 6, 7, 8]),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result as AnyResult;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+mod jks;
+mod pkcs12;
+
+/// 0xFEEDFEED
+const JKS_MAGIC: [u8; 4] = [0xFE, 0xED, 0xFE, 0xED];
+/// 0xCECECECE
+const JCEKS_MAGIC: [u8; 4] = [0xCE, 0xCE, 0xCE, 0xCE];
+/// The highest iteration count of the key derivations. The keystores are read again on every
+/// interval, so a crafted file shouldn't keep a worker busy, while the defaults of OpenSSL and
+/// keytool range from 2048 to 200000.
+const MAX_ITERATIONS: u64 = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeystoreFormat {
+    Pkcs12,
+    Jks,
+    Jceks,
+}
+
+impl KeystoreFormat {
+    /// Guess the format from the beginning of the file, or `None` if it isn't a keystore.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&JKS_MAGIC) {
+            return Some(KeystoreFormat::Jks);
+        }
+        if data.starts_with(&JCEKS_MAGIC) {
+            return Some(KeystoreFormat::Jceks);
+        }
+        pkcs12::is_pfx(data).then_some(KeystoreFormat::Pkcs12)
+    }
+}
+
+/// A private key with its certificate chain, or a trusted certificate
+#[derive(Debug)]
+pub struct KeystoreEntry {
+    pub alias: String,
+    /// The certificate of the key comes first, followed by its issuers
+    pub certificates: Vec<CertificateDer<'static>>,
+    pub key: Option<PrivateKeyDer<'static>>,
+}
+
+/// The entries read from a PKCS#12, JKS or JCEKS keystore
+#[derive(Debug, Default)]
+pub struct Keystore {
+    pub entries: Vec<KeystoreEntry>,
+}
+
+impl Keystore {
+    /// Parse a keystore, whose integrity is checked if a password is given.
+    ///
+    /// The private keys are only decrypted with the keystore password, so keys protected by a
+    /// different password can't be read.
+    pub fn parse(data: &[u8], password: Option<&str>) -> AnyResult<Self> {
+        let entries = match KeystoreFormat::detect(data) {
+            Some(KeystoreFormat::Jks) | Some(KeystoreFormat::Jceks) => jks::parse(data, password)?,
+            // Try anything else as PKCS#12, which gives a better error than guessing
+            Some(KeystoreFormat::Pkcs12) | None => {
+                pkcs12::parse(data, password.unwrap_or_default())?
+            }
+        };
+        Ok(Self { entries })
+    }
+
+    /// Keep only the entry with the given alias.
+    pub fn select(&mut self, alias: &str) {
+        self.entries.retain(|entry| entry.alias == alias);
+    }
+
+    /// The first entry which has a private key, e.g. to use as the client certificate
+    pub fn into_key_entry(self) -> Option<KeystoreEntry> {
+        self.entries.into_iter().find(|entry| entry.key.is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Generated by `fixtures/generate.sh`
+    const PBES2_P12: &[u8] = include_bytes!("fixtures/pbes2.p12");
+    const LEGACY_P12: &[u8] = include_bytes!("fixtures/legacy.p12");
+    const KEYTOOL_P12: &[u8] = include_bytes!("fixtures/keytool.p12");
+    const NOPASS_P12: &[u8] = include_bytes!("fixtures/nopass.p12");
+    const JKS: &[u8] = include_bytes!("fixtures/keystore.jks");
+    const JCEKS: &[u8] = include_bytes!("fixtures/keystore.jceks");
+
+    /// The aliases of the entries with the length of their chain and whether they have a key
+    fn summary(keystore: &Keystore) -> Vec<(&str, usize, bool)> {
+        let mut summary: Vec<_> = keystore
+            .entries
+            .iter()
+            .map(|entry| {
+                let key = match &entry.key {
+                    Some(PrivateKeyDer::Pkcs8(key)) => {
+                        // The decrypted key is a PKCS#8 P-256 key
+                        ring::signature::EcdsaKeyPair::from_pkcs8(
+                            &ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+                            key.secret_pkcs8_der(),
+                            &ring::rand::SystemRandom::new(),
+                        )
+                        .unwrap();
+                        true
+                    }
+                    Some(_) => panic!("unexpected key format"),
+                    None => false,
+                };
+                (entry.alias.as_str(), entry.certificates.len(), key)
+            })
+            .collect();
+        summary.sort();
+        summary
+    }
+
+    #[test]
+    fn parse_pkcs12() {
+        let leaf = ("leaf", 2, true);
+        for (data, password, expected) in [
+            (PBES2_P12, "secret", vec![leaf]),
+            (LEGACY_P12, "secret", vec![leaf]),
+            // The trusted certificate of the JKS keystore is also in the chain of the key
+            (KEYTOOL_P12, "changeit", vec![("ca", 1, false), leaf]),
+        ] {
+            assert_eq!(KeystoreFormat::detect(data), Some(KeystoreFormat::Pkcs12));
+            let keystore = Keystore::parse(data, Some(password)).unwrap();
+            assert_eq!(summary(&keystore), expected, "{}", password);
+            assert!(Keystore::parse(data, Some("wrong")).is_err());
+        }
+
+        let keystore = Keystore::parse(NOPASS_P12, None).unwrap();
+        assert_eq!(summary(&keystore), [("", 1, false)]);
+    }
+
+    #[test]
+    fn parse_java_keystores() {
+        for (data, format) in [(JKS, KeystoreFormat::Jks), (JCEKS, KeystoreFormat::Jceks)] {
+            assert_eq!(KeystoreFormat::detect(data), Some(format));
+            // The secret key of the JCEKS keystore is skipped
+            let keystore = Keystore::parse(data, Some("changeit")).unwrap();
+            assert_eq!(
+                summary(&keystore),
+                [("ca", 1, false), ("leaf", 2, true)],
+                "{:?}",
+                format
+            );
+            let ca = &keystore.entries.iter().find(|e| e.alias == "ca").unwrap();
+            let leaf = &keystore.entries.iter().find(|e| e.alias == "leaf").unwrap();
+            assert_eq!(leaf.certificates[1], ca.certificates[0]);
+
+            // Without a password, neither the integrity nor the keys are checked
+            let keystore = Keystore::parse(data, None).unwrap();
+            assert_eq!(summary(&keystore), [("ca", 1, false), ("leaf", 2, false)]);
+            assert!(Keystore::parse(data, Some("wrong")).is_err());
+        }
+    }
+}
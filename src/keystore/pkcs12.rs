@@ -0,0 +1,624 @@
+//! PKCS#12 following RFC 7292, with the password-based encryption schemes of PKCS#12 and
+//! PBES2 from RFC 8018.
+
+use super::{KeystoreEntry, MAX_ITERATIONS};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use bcder::{
+    decode::{Constructed, DecodeError, Source},
+    Captured, ConstOid, Mode, OctetString, Oid, Tag,
+};
+use bytes::Bytes;
+use cbc::cipher::{block_padding::Pkcs7, BlockCipher, BlockDecryptMut, InnerIvInit, KeyInit};
+use ring::{digest, hmac, pbkdf2};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::num::NonZeroU32;
+use x509_certificate::X509Certificate;
+
+/// 1.2.840.113549.1.7.1
+const OID_DATA: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 7, 1]);
+/// 1.2.840.113549.1.7.6
+const OID_ENCRYPTED_DATA: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 7, 6]);
+/// 1.2.840.113549.1.12.10.1.1
+const OID_KEY_BAG: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 10, 1, 1]);
+/// 1.2.840.113549.1.12.10.1.2
+const OID_SHROUDED_KEY_BAG: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 10, 1, 2]);
+/// 1.2.840.113549.1.12.10.1.3
+const OID_CERT_BAG: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 10, 1, 3]);
+/// 1.2.840.113549.1.9.22.1
+const OID_X509_CERTIFICATE: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 22, 1]);
+/// 1.2.840.113549.1.9.20
+const OID_FRIENDLY_NAME: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 20]);
+/// 1.2.840.113549.1.9.21
+const OID_LOCAL_KEY_ID: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 21]);
+
+/// 1.2.840.113549.1.12.1.3
+const OID_PBE_SHA1_3DES: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 1, 3]);
+/// 1.2.840.113549.1.12.1.5
+const OID_PBE_SHA1_RC2_128: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 1, 5]);
+/// 1.2.840.113549.1.12.1.6
+const OID_PBE_SHA1_RC2_40: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 12, 1, 6]);
+/// 1.2.840.113549.1.5.13
+const OID_PBES2: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 5, 13]);
+/// 1.2.840.113549.1.5.12
+const OID_PBKDF2: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 5, 12]);
+/// 1.2.840.113549.2.7
+const OID_HMAC_SHA1: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 2, 7]);
+/// 1.2.840.113549.2.9
+const OID_HMAC_SHA256: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 2, 9]);
+/// 1.2.840.113549.2.10
+const OID_HMAC_SHA384: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 2, 10]);
+/// 1.2.840.113549.2.11
+const OID_HMAC_SHA512: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 2, 11]);
+/// 2.16.840.1.101.3.4.1.2
+const OID_AES128_CBC: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 1, 2]);
+/// 2.16.840.1.101.3.4.1.22
+const OID_AES192_CBC: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 1, 22]);
+/// 2.16.840.1.101.3.4.1.42
+const OID_AES256_CBC: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 1, 42]);
+/// 1.2.840.113549.3.7
+const OID_DES_EDE3_CBC: ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 3, 7]);
+
+/// 1.3.14.3.2.26
+const OID_SHA1: ConstOid = Oid(&[43, 14, 3, 2, 26]);
+/// 2.16.840.1.101.3.4.2.1
+const OID_SHA256: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 2, 1]);
+/// 2.16.840.1.101.3.4.2.2
+const OID_SHA384: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 2, 2]);
+/// 2.16.840.1.101.3.4.2.3
+const OID_SHA512: ConstOid = Oid(&[96, 134, 72, 1, 101, 3, 4, 2, 3]);
+
+/// The diversifiers of the key derivation in appendix B of RFC 7292
+const KDF_KEY: u8 = 1;
+const KDF_IV: u8 = 2;
+const KDF_MAC: u8 = 3;
+
+fn invalid(reason: &str) -> ErrorReason {
+    ErrorReason::InvalidKeystore(format!("PKCS#12: {}", reason))
+}
+
+/// Whether the data starts like a `PFX`, i.e. a SEQUENCE starting with the version 3, while a
+/// certificate starts with another SEQUENCE.
+pub fn is_pfx(data: &[u8]) -> bool {
+    let header_len = match data {
+        [0x30, len, ..] if len & 0x80 == 0 || *len == 0x80 => 2,
+        [0x30, len, ..] => 2 + (len & 0x7F) as usize,
+        _ => return false,
+    };
+    data.get(header_len..header_len + 3) == Some(&[0x02, 0x01, 0x03])
+}
+
+struct MacData {
+    algorithm: Oid<Bytes>,
+    digest: Bytes,
+    salt: Bytes,
+    iterations: u64,
+}
+
+#[derive(Default)]
+struct SafeBag {
+    bag_id: Option<Oid<Bytes>>,
+    value: Option<Captured>,
+    friendly_name: Option<String>,
+    local_key_id: Option<Bytes>,
+}
+
+struct Certificate {
+    der: CertificateDer<'static>,
+    parsed: Option<X509Certificate>,
+    friendly_name: Option<String>,
+    local_key_id: Option<Bytes>,
+    used: bool,
+}
+
+pub fn parse(data: &[u8], password: &str) -> AnyResult<Vec<KeystoreEntry>> {
+    let (auth_safe, mac) = Mode::Ber.decode(data, |cons| {
+        cons.take_sequence(|cons| {
+            cons.skip_u8_if(3)?;
+            let auth_safe = take_data(cons)?;
+            let mac = cons.take_opt_sequence(|cons| {
+                let (algorithm, digest) = cons.take_sequence(|cons| {
+                    let (algorithm, _params) = take_algorithm(cons)?;
+                    Ok((algorithm, OctetString::take_from(cons)?.to_bytes()))
+                })?;
+                Ok(MacData {
+                    algorithm,
+                    digest,
+                    salt: OctetString::take_from(cons)?.to_bytes(),
+                    iterations: cons.take_opt_u64()?.unwrap_or(1),
+                })
+            })?;
+            Ok((auth_safe, mac))
+        })
+    })?;
+    if let Some(mac) = &mac {
+        check_iterations(mac.iterations)?;
+    }
+
+    // Some implementations encode an empty password without the terminating NUL
+    let mut passwords = vec![bmp_password(password)];
+    if password.is_empty() {
+        passwords.push(Vec::new());
+    }
+    let password = match &mac {
+        Some(mac) => passwords
+            .into_iter()
+            .find(|password| verify_mac(mac, &auth_safe, password))
+            .ok_or_else(|| invalid("the password is incorrect or the keystore is corrupted"))?,
+        None => passwords.swap_remove(0),
+    };
+
+    let contents = Mode::Ber.decode(auth_safe.as_ref(), |cons| {
+        cons.take_sequence(|cons| {
+            let mut contents = Vec::new();
+            while let Some(content) = cons.take_opt_sequence(|cons| {
+                let content_type = Oid::take_from(cons)?;
+                let content = cons.take_constructed_if(Tag::CTX_0, |cons| cons.capture_one())?;
+                Ok((content_type, content))
+            })? {
+                contents.push(content);
+            }
+            Ok(contents)
+        })
+    })?;
+
+    let mut bags = Vec::new();
+    for (content_type, content) in contents {
+        let safe_contents = if content_type == OID_DATA {
+            content.decode(OctetString::take_from)?.to_bytes()
+        } else if content_type == OID_ENCRYPTED_DATA {
+            let (algorithm, params, encrypted) = content.decode(|cons| {
+                cons.take_sequence(|cons| {
+                    // version
+                    cons.take_u8()?;
+                    cons.take_sequence(|cons| {
+                        Oid::take_from(cons)?;
+                        let (algorithm, params) = take_algorithm(cons)?;
+                        let encrypted = cons
+                            .take_opt_value_if(Tag::CTX_0, OctetString::from_content)?
+                            .map(|encrypted| encrypted.to_bytes())
+                            .unwrap_or_default();
+                        Ok((algorithm, params, encrypted))
+                    })
+                })
+            })?;
+            decrypt(&algorithm, params.as_slice(), &encrypted, &password)?.into()
+        } else {
+            // e.g. the enveloped data encrypted with a public key
+            warn!("Skip unsupported PKCS#12 content {}", content_type);
+            continue;
+        };
+        bags.extend(decode_safe_contents(&safe_contents)?);
+    }
+
+    let mut keys = Vec::new();
+    let mut certificates = Vec::new();
+    for bag in bags {
+        let (Some(bag_id), Some(value)) = (bag.bag_id, bag.value) else {
+            continue;
+        };
+        if bag_id == OID_KEY_BAG {
+            keys.push((
+                bag.friendly_name,
+                bag.local_key_id,
+                value.as_slice().to_vec(),
+            ));
+        } else if bag_id == OID_SHROUDED_KEY_BAG {
+            let (algorithm, params, encrypted) =
+                decode_encrypted_private_key_info(value.as_slice())?;
+            let key = decrypt(&algorithm, params.as_slice(), &encrypted, &password)?;
+            keys.push((bag.friendly_name, bag.local_key_id, key));
+        } else if bag_id == OID_CERT_BAG {
+            let (cert_type, der) = value.decode(|cons| {
+                cons.take_sequence(|cons| {
+                    let cert_type = Oid::take_from(cons)?;
+                    let der = cons.take_constructed_if(Tag::CTX_0, OctetString::take_from)?;
+                    Ok((cert_type, der.to_bytes()))
+                })
+            })?;
+            if cert_type == OID_X509_CERTIFICATE {
+                certificates.push(Certificate {
+                    parsed: X509Certificate::from_der(&der).ok(),
+                    der: CertificateDer::from(der.to_vec()),
+                    friendly_name: bag.friendly_name,
+                    local_key_id: bag.local_key_id,
+                    used: false,
+                });
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (friendly_name, local_key_id, key) in keys {
+        let index = certificates.iter().position(|cert| {
+            !cert.used && (local_key_id.is_none() || cert.local_key_id == local_key_id)
+        });
+        let chain = index.map_or_else(Vec::new, |index| build_chain(&mut certificates, index));
+        let alias = friendly_name
+            .or_else(|| index.and_then(|index| certificates[index].friendly_name.clone()))
+            .unwrap_or_default();
+        entries.push(KeystoreEntry {
+            alias,
+            certificates: chain,
+            key: Some(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key))),
+        });
+    }
+    // The certificates which don't belong to a key, e.g. the ones of a truststore
+    for cert in certificates.into_iter().filter(|cert| !cert.used) {
+        entries.push(KeystoreEntry {
+            alias: cert.friendly_name.unwrap_or_default(),
+            certificates: vec![cert.der],
+            key: None,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Follow the issuers of the certificate among the ones which are not in a chain yet.
+fn build_chain(certificates: &mut [Certificate], mut index: usize) -> Vec<CertificateDer<'static>> {
+    let mut chain = Vec::new();
+    loop {
+        certificates[index].used = true;
+        chain.push(certificates[index].der.clone());
+
+        let Some(cert) = certificates[index].parsed.as_ref() else {
+            break;
+        };
+        if cert.subject_name() == cert.issuer_name() {
+            break;
+        }
+        let issuer = cert.issuer_name().clone();
+        match certificates.iter().position(|cert| {
+            !cert.used
+                && cert
+                    .parsed
+                    .as_ref()
+                    .map_or(false, |parsed| parsed.subject_name() == &issuer)
+        }) {
+            Some(next) => index = next,
+            None => break,
+        }
+    }
+    chain
+}
+
+fn decode_safe_contents(data: &[u8]) -> AnyResult<Vec<SafeBag>> {
+    let bags = Mode::Ber.decode(data, |cons| {
+        cons.take_sequence(|cons| {
+            let mut bags = Vec::new();
+            while let Some(bag) = cons.take_opt_sequence(take_safe_bag)? {
+                bags.push(bag);
+            }
+            Ok(bags)
+        })
+    })?;
+    Ok(bags)
+}
+
+fn take_safe_bag<S: Source>(cons: &mut Constructed<S>) -> Result<SafeBag, DecodeError<S::Error>> {
+    let mut bag = SafeBag {
+        bag_id: Some(Oid::take_from(cons)?),
+        value: Some(cons.take_constructed_if(Tag::CTX_0, |cons| cons.capture_one())?),
+        ..Default::default()
+    };
+    cons.take_opt_set(|cons| {
+        while let Some(()) = cons.take_opt_sequence(|cons| {
+            let attribute = Oid::take_from(cons)?;
+            cons.take_set(|cons| {
+                if attribute == OID_FRIENDLY_NAME {
+                    let name = cons.take_value_if(Tag::BMP_STRING, |content| {
+                        content.as_primitive()?.take_all()
+                    })?;
+                    let name: Vec<u16> = name
+                        .chunks(2)
+                        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                        .collect();
+                    bag.friendly_name = Some(String::from_utf16_lossy(&name));
+                } else if attribute == OID_LOCAL_KEY_ID {
+                    bag.local_key_id = Some(OctetString::take_from(cons)?.to_bytes());
+                }
+                cons.skip_all()
+            })
+        })? {}
+        Ok(())
+    })?;
+    Ok(bag)
+}
+
+/// The content of a `ContentInfo` of the data type
+fn take_data<S: Source>(cons: &mut Constructed<S>) -> Result<Bytes, DecodeError<S::Error>> {
+    cons.take_sequence(|cons| {
+        if Oid::take_from(cons)? != OID_DATA {
+            return Err(cons.content_err("unsupported authenticated safe"));
+        }
+        let data = cons.take_constructed_if(Tag::CTX_0, OctetString::take_from)?;
+        Ok(data.to_bytes())
+    })
+}
+
+/// An `AlgorithmIdentifier`, whose parameters are kept undecoded
+fn take_algorithm<S: Source>(
+    cons: &mut Constructed<S>,
+) -> Result<(Oid<Bytes>, Captured), DecodeError<S::Error>> {
+    cons.take_sequence(|cons| Ok((Oid::take_from(cons)?, cons.capture_all()?)))
+}
+
+/// Decode an `EncryptedPrivateKeyInfo` of PKCS#8 into the algorithm, its parameters and the
+/// encrypted key.
+pub fn decode_encrypted_private_key_info(der: &[u8]) -> AnyResult<(Oid<Bytes>, Captured, Bytes)> {
+    let info = Mode::Ber.decode(der, |cons| {
+        cons.take_sequence(|cons| {
+            let (algorithm, params) = take_algorithm(cons)?;
+            Ok((algorithm, params, OctetString::take_from(cons)?.to_bytes()))
+        })
+    })?;
+    Ok(info)
+}
+
+/// Decode the `PBEParameter`, i.e. the salt and the iteration count.
+pub fn decode_pbe_params(params: &[u8]) -> AnyResult<(Bytes, u64)> {
+    let (salt, iterations) = Mode::Ber.decode(params, |cons| {
+        cons.take_sequence(|cons| Ok((OctetString::take_from(cons)?.to_bytes(), cons.take_u64()?)))
+    })?;
+    check_iterations(iterations)?;
+    Ok((salt, iterations))
+}
+
+/// Reject the iteration counts above [`MAX_ITERATIONS`] before deriving a key.
+fn check_iterations(iterations: u64) -> AnyResult<()> {
+    if iterations > MAX_ITERATIONS {
+        return Err(invalid(&format!("too many iterations {}", iterations)).into());
+    }
+    Ok(())
+}
+
+fn verify_mac(mac: &MacData, data: &[u8], password: &[u8]) -> bool {
+    let (digest_algorithm, hmac_algorithm) = if mac.algorithm == OID_SHA1 {
+        (
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        )
+    } else if mac.algorithm == OID_SHA256 {
+        (&digest::SHA256, hmac::HMAC_SHA256)
+    } else if mac.algorithm == OID_SHA384 {
+        (&digest::SHA384, hmac::HMAC_SHA384)
+    } else if mac.algorithm == OID_SHA512 {
+        (&digest::SHA512, hmac::HMAC_SHA512)
+    } else {
+        warn!("Unsupported PKCS#12 MAC algorithm {}", mac.algorithm);
+        return false;
+    };
+
+    let key = pkcs12_kdf(
+        digest_algorithm,
+        password,
+        &mac.salt,
+        mac.iterations,
+        KDF_MAC,
+        digest_algorithm.output_len(),
+    );
+    hmac::verify(&hmac::Key::new(hmac_algorithm, &key), data, &mac.digest).is_ok()
+}
+
+/// Decrypt with one of the password-based encryption schemes, where `password` is encoded as
+/// a `BMPString` for the schemes of PKCS#12.
+fn decrypt(algorithm: &Oid, params: &[u8], data: &[u8], password: &[u8]) -> AnyResult<Vec<u8>> {
+    if *algorithm == OID_PBES2 {
+        return decrypt_pbes2(params, data, password);
+    }
+
+    let (salt, iterations) = decode_pbe_params(params)?;
+    let sha1 = &digest::SHA1_FOR_LEGACY_USE_ONLY;
+    let key = |len| pkcs12_kdf(sha1, password, &salt, iterations, KDF_KEY, len);
+    let iv = pkcs12_kdf(sha1, password, &salt, iterations, KDF_IV, 8);
+    if *algorithm == OID_PBE_SHA1_3DES {
+        let cipher = des::TdesEde3::new_from_slice(&key(24)).map_err(|_| invalid("key length"))?;
+        cbc_decrypt(cipher, &iv, data)
+    } else if *algorithm == OID_PBE_SHA1_RC2_128 {
+        cbc_decrypt(rc2::Rc2::new_with_eff_key_len(&key(16), 128), &iv, data)
+    } else if *algorithm == OID_PBE_SHA1_RC2_40 {
+        cbc_decrypt(rc2::Rc2::new_with_eff_key_len(&key(5), 40), &iv, data)
+    } else {
+        Err(invalid(&format!("unsupported encryption {}", algorithm)).into())
+    }
+}
+
+fn decrypt_pbes2(params: &[u8], data: &[u8], password: &[u8]) -> AnyResult<Vec<u8>> {
+    let ((kdf, kdf_params), (cipher, cipher_params)) = Mode::Ber.decode(params, |cons| {
+        cons.take_sequence(|cons| Ok((take_algorithm(cons)?, take_algorithm(cons)?)))
+    })?;
+    if kdf != OID_PBKDF2 {
+        return Err(invalid(&format!("unsupported key derivation {}", kdf)).into());
+    }
+    let (salt, iterations, prf) = kdf_params.decode(|cons| {
+        cons.take_sequence(|cons| {
+            let salt = OctetString::take_from(cons)?.to_bytes();
+            let iterations = cons.take_u32()?;
+            // keyLength
+            cons.take_opt_u64()?;
+            let prf = cons.take_opt_sequence(|cons| {
+                let prf = Oid::take_from(cons)?;
+                cons.skip_all()?;
+                Ok(prf)
+            })?;
+            Ok((salt, iterations, prf))
+        })
+    })?;
+    let iv = cipher_params.decode(OctetString::take_from)?.to_bytes();
+
+    let prf = match prf {
+        None => pbkdf2::PBKDF2_HMAC_SHA1,
+        Some(prf) if prf == OID_HMAC_SHA1 => pbkdf2::PBKDF2_HMAC_SHA1,
+        Some(prf) if prf == OID_HMAC_SHA256 => pbkdf2::PBKDF2_HMAC_SHA256,
+        Some(prf) if prf == OID_HMAC_SHA384 => pbkdf2::PBKDF2_HMAC_SHA384,
+        Some(prf) if prf == OID_HMAC_SHA512 => pbkdf2::PBKDF2_HMAC_SHA512,
+        Some(prf) => return Err(invalid(&format!("unsupported PRF {}", prf)).into()),
+    };
+    check_iterations(iterations.into())?;
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| invalid("zero iterations"))?;
+    let key_len = if cipher == OID_AES128_CBC {
+        16
+    } else if cipher == OID_AES192_CBC || cipher == OID_DES_EDE3_CBC {
+        24
+    } else if cipher == OID_AES256_CBC {
+        32
+    } else {
+        return Err(invalid(&format!("unsupported encryption {}", cipher)).into());
+    };
+
+    // PBES2 takes the password as is, instead of a BMPString
+    let password = utf8_password(password);
+    let mut key = vec![0; key_len];
+    pbkdf2::derive(prf, iterations, &salt, &password, &mut key);
+
+    let invalid_key = |_| invalid("key length");
+    if cipher == OID_AES128_CBC {
+        cbc_decrypt(
+            aes::Aes128::new_from_slice(&key).map_err(invalid_key)?,
+            &iv,
+            data,
+        )
+    } else if cipher == OID_AES192_CBC {
+        cbc_decrypt(
+            aes::Aes192::new_from_slice(&key).map_err(invalid_key)?,
+            &iv,
+            data,
+        )
+    } else if cipher == OID_AES256_CBC {
+        cbc_decrypt(
+            aes::Aes256::new_from_slice(&key).map_err(invalid_key)?,
+            &iv,
+            data,
+        )
+    } else {
+        cbc_decrypt(
+            des::TdesEde3::new_from_slice(&key).map_err(invalid_key)?,
+            &iv,
+            data,
+        )
+    }
+}
+
+fn cbc_decrypt<C>(cipher: C, iv: &[u8], data: &[u8]) -> AnyResult<Vec<u8>>
+where
+    C: BlockCipher + BlockDecryptMut,
+{
+    let mut buf = data.to_vec();
+    let len = cbc::Decryptor::inner_iv_slice_init(cipher, iv)
+        .map_err(|_| invalid("IV length"))?
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .map_err(|_| invalid("the password is incorrect"))?
+        .len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// The key derivation of appendix B of RFC 7292.
+fn pkcs12_kdf(
+    algorithm: &'static digest::Algorithm,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u64,
+    id: u8,
+    len: usize,
+) -> Vec<u8> {
+    let block_len = algorithm.block_len();
+    // Repeat to fill a multiple of the block length
+    let fill = |data: &[u8]| -> Vec<u8> {
+        let len = (data.len() + block_len - 1) / block_len * block_len;
+        data.iter().cycle().take(len).copied().collect()
+    };
+    let mut input = [fill(salt), fill(password)].concat();
+
+    let mut output = Vec::with_capacity(len);
+    while output.len() < len {
+        let mut context = digest::Context::new(algorithm);
+        context.update(&vec![id; block_len]);
+        context.update(&input);
+        let mut hash = context.finish();
+        for _ in 1..iterations {
+            hash = digest::digest(algorithm, hash.as_ref());
+        }
+        output.extend_from_slice(hash.as_ref());
+
+        // Add the hash and 1 to each block of the input
+        let hash = fill(hash.as_ref());
+        for block in input.chunks_mut(block_len) {
+            let mut carry = 1;
+            for (byte, add) in block.iter_mut().zip(&hash).rev() {
+                let sum = *byte as u16 + *add as u16 + carry;
+                *byte = sum as u8;
+                carry = sum >> 8;
+            }
+        }
+    }
+    output.truncate(len);
+    output
+}
+
+/// The password as a NUL-terminated `BMPString`
+fn bmp_password(password: &str) -> Vec<u8> {
+    password
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// Convert a password from [`bmp_password`] back to UTF-8.
+fn utf8_password(password: &[u8]) -> Vec<u8> {
+    let chars: Vec<u16> = password
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars).into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derive_pkcs12_keys() {
+        // The test vectors of Bouncy Castle
+        let password = bmp_password("smeg");
+        let salt = [0x0A, 0x58, 0xCF, 0x64, 0x53, 0x0D, 0x82, 0x3F];
+        let sha1 = &digest::SHA1_FOR_LEGACY_USE_ONLY;
+        assert_eq!(
+            pkcs12_kdf(sha1, &password, &salt, 1, KDF_KEY, 24),
+            [
+                0x8A, 0xAA, 0xE6, 0x29, 0x7B, 0x6C, 0xB0, 0x46, 0x42, 0xAB, 0x5B, 0x07, 0x78, 0x51,
+                0x28, 0x4E, 0xB7, 0x12, 0x8F, 0x1A, 0x2A, 0x7F, 0xBC, 0xA3
+            ]
+        );
+        assert_eq!(
+            pkcs12_kdf(sha1, &password, &salt, 1, KDF_IV, 8),
+            [0x79, 0x99, 0x3D, 0xFE, 0x04, 0x8D, 0x3B, 0x76]
+        );
+    }
+
+    #[test]
+    fn limit_iterations() {
+        let params = |iterations: &[u8]| {
+            let mut der = vec![0x30, 12 + iterations.len() as u8, 0x04, 0x08];
+            der.extend([0; 8]);
+            der.extend([0x02, iterations.len() as u8]);
+            der.extend(iterations);
+            der
+        };
+        assert_eq!(decode_pbe_params(&params(&[0x08, 0x00])).unwrap().1, 2048);
+        // 2000000
+        assert!(decode_pbe_params(&params(&[0x1E, 0x84, 0x80])).is_err());
+    }
+
+    #[test]
+    fn detect_pfx() {
+        assert!(is_pfx(&[0x30, 0x82, 0x01, 0x00, 0x02, 0x01, 0x03]));
+        assert!(is_pfx(&[0x30, 0x80, 0x02, 0x01, 0x03]));
+        // A certificate
+        assert!(!is_pfx(&[0x30, 0x82, 0x01, 0x00, 0x30, 0x82, 0x00, 0xF0]));
+        assert!(!is_pfx(b"-----BEGIN CERTIFICATE-----"));
+    }
+}
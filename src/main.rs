@@ -1,32 +1,45 @@
 #[macro_use]
 extern crate tracing;
 
-use crate::configs::GlobalConfig;
+use crate::configs::{CliArgs, GlobalConfig};
 use anyhow::Result as AnyResult;
-use components::{MetricsExporter, ProbeScheduler};
+use clap::Parser;
+use components::{ConfigReloader, MetricsExporter, ProbeScheduler};
 use configs::ConnectionParameters;
 use hickory_resolver::AsyncResolver;
 use prober::Prober;
-use std::{num::NonZeroUsize, sync::Arc};
+use std::{collections::HashMap, num::NonZeroUsize, sync::Arc};
 use store::Store;
-use tokio::{sync::RwLock, task::JoinSet};
+use tokio::{
+    sync::{watch, RwLock},
+    task::JoinSet,
+    time::timeout,
+};
 
 mod cert;
 mod certificate_interceptor;
 mod components;
 mod configs;
 mod error;
+mod keystore;
 mod prober;
+mod scanner;
+mod starttls;
 mod state;
 mod store;
+#[cfg(test)]
+mod testing;
 
 fn main() -> AnyResult<()> {
+    let args = CliArgs::parse();
+
     // Load environment variables from the `.env` file
     dotenvy::dotenv().ok();
     // Initialize the logger after loading the environment variables
     tracing_subscriber::fmt::init();
 
-    let app_config = GlobalConfig::load_config().expect("Failed to parse configuration files");
+    let mut app_config = GlobalConfig::load_config().expect("Failed to parse configuration files");
+    args.apply(&mut app_config);
 
     // Setup async runtime
     let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
@@ -44,21 +57,88 @@ async fn async_main(app_config: GlobalConfig) -> AnyResult<()> {
     let default_params = ConnectionParameters::load_from_global_config(&app_config).await?;
 
     let resolver = Arc::new(AsyncResolver::tokio_from_system_conf()?);
-    let store = Arc::new(RwLock::new(Store::default()));
+    let mut store = Store::new(app_config.store.clone());
+    if let Err(e) = store.update_configured_certificates(None, &default_params) {
+        warn!("Failed to parse the trust anchors: {:#}", e);
+    }
+    let store = Arc::new(RwLock::new(store));
     let prober = Arc::new(Prober::new(resolver.clone(), default_params));
 
     let mut scheduler =
         ProbeScheduler::new(prober.clone(), store.clone(), app_config.scheduler.clone());
-    let metrics_exporter = MetricsExporter::new(store.clone())?;
+
+    let mut modules = HashMap::new();
+    for (name, module_config) in &app_config.modules {
+        let conn_params = ConnectionParameters::load_from_module_config(module_config).await?;
+        modules.insert(name.clone(), conn_params);
+    }
+    let metrics_exporter = MetricsExporter::new(
+        store.clone(),
+        prober.clone(),
+        modules,
+        app_config.web.clone(),
+        app_config.metrics.clone(),
+    )?;
+    let config_reloader = ConfigReloader::new(
+        scheduler.command_sender(),
+        metrics_exporter.registry(),
+        &app_config.metrics,
+    )?;
+    let metrics_exporter = metrics_exporter.with_reload_handle(config_reloader.handle());
 
     for target_config in &app_config.targets {
         scheduler.load_from_target_config(target_config).await?;
     }
+    let file_sources = ProbeScheduler::load_file_sources(&app_config.files)?;
+    scheduler.update_file_sources(file_sources).await;
 
+    let (shutdown_tx, shutdown) = watch::channel(());
     let mut set = JoinSet::new();
-    set.spawn(async move { scheduler.run().await });
-    set.spawn(async move { metrics_exporter.run().await });
-    set.join_next().await;
+    {
+        let shutdown = shutdown.clone();
+        set.spawn(async move { scheduler.run(shutdown).await });
+    }
+    {
+        let shutdown = shutdown.clone();
+        set.spawn(async move { metrics_exporter.run(shutdown).await });
+    }
+    set.spawn(config_reloader.run(shutdown));
+
+    // A task which stopped by itself failed, e.g. to listen on the configured addresses
+    let result = tokio::select! {
+        result = set.join_next() => match result {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(e.into()),
+            None => Ok(()),
+        },
+        result = shutdown_signal() => result,
+    };
+
+    info!("Shutting down");
+    shutdown_tx.send(()).ok();
+    let drain = async { while set.join_next().await.is_some() {} };
+    if timeout(app_config.shutdown_timeout, drain).await.is_err() {
+        warn!("Some tasks didn't finish in time, abort them");
+        set.shutdown().await;
+    }
+
+    result
+}
+
+/// Wait for SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() -> AnyResult<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
 
     Ok(())
 }
@@ -1,18 +1,27 @@
 use crate::{
-    cert::ParsedCertificate,
+    cert::{CertificateRevocationList, OcspResponse, ParsedCertificate},
     configs::{ConnectionParameters, DEFAULT_TIMEOUT},
-    error::ErrorReason,
+    error::{ErrorClass, ErrorReason, VerifyReason},
+    scanner::{self, ScanResult},
     store::{Endpoint, Target},
 };
 use anyhow::{Context, Result as AnyResult};
 use futures::{stream::FuturesUnordered, TryStreamExt};
 use hickory_resolver::TokioAsyncResolver;
 use std::{
+    fmt::{Display, Formatter},
     io::{Error as IoError, ErrorKind as IoErrorKind},
     sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    time::{timeout, Instant},
+};
+use tokio_rustls::{
+    rustls::{CommonState, ProtocolVersion},
+    TlsConnector,
 };
-use tokio::{net::TcpStream, time::timeout};
-use tokio_rustls::TlsConnector;
 use x509_certificate::X509Certificate;
 
 #[derive(Debug)]
@@ -29,6 +38,14 @@ impl Prober {
         }
     }
 
+    /// The timeout of each phase of the probe with the parameters
+    pub fn timeout(&self, parameters: &ConnectionParameters) -> Duration {
+        parameters
+            .timeout
+            .or(self.default_params.timeout)
+            .unwrap_or(DEFAULT_TIMEOUT)
+    }
+
     pub async fn probe(
         &self,
         target: &Target,
@@ -36,83 +53,449 @@ impl Prober {
     ) -> AnyResult<Vec<ProbeResult>> {
         let params = parameters.merge(&self.default_params);
 
+        let resolve_start = Instant::now();
         let endpoints = timeout(
             params.timeout.unwrap_or(DEFAULT_TIMEOUT),
             Endpoint::resolve(target, &self.resolver),
         )
         .await
         .with_context(|| "Name resolution timeout")??;
+        let resolve_duration = resolve_start.elapsed();
+
+        let endpoints: Vec<Endpoint> = if params.server_names.is_empty() {
+            endpoints
+        } else {
+            endpoints
+                .iter()
+                .flat_map(|ep| {
+                    params.server_names.iter().map(|server_name| Endpoint {
+                        sockaddr: ep.sockaddr,
+                        server_name: server_name.clone(),
+                    })
+                })
+                .collect()
+        };
 
         let tasks: FuturesUnordered<_> = endpoints
             .into_iter()
             .map(|ep| {
                 // Borrow before `move` block
                 let params_ref = &params;
-                async move { Self::probe_endpoint(&ep, params_ref).await }
+                async move {
+                    let mut result = Self::probe_endpoint(&ep, params_ref).await?;
+                    result.duration.resolve = resolve_duration;
+                    Ok::<_, anyhow::Error>(result)
+                }
             })
             .collect();
         tasks.try_collect().await
     }
 
+    /// Probe the endpoint and collect its certificates.
+    ///
+    /// Connection failures are reported in [`ProbeResult::probe_result`], while `Err` is
+    /// only returned when the probe cannot be started at all.
     pub async fn probe_endpoint(
         endpoint: &Endpoint,
         parameters: &ConnectionParameters,
     ) -> AnyResult<ProbeResult> {
+        // The scan has to finish within the timeout as well
+        let scan_deadline = Instant::now() + parameters.timeout.unwrap_or(DEFAULT_TIMEOUT);
         let (tls_config, mut interceptor) = parameters.build_tls_config()?;
         let connector = TlsConnector::from(Arc::new(tls_config));
-        let stream = TcpStream::connect(&endpoint.sockaddr).await?;
 
-        let conn_result = match timeout(
-            parameters.timeout.unwrap_or(DEFAULT_TIMEOUT),
-            connector.connect(endpoint.server_name.clone(), stream),
-        )
-        .await
-        {
-            Ok(conn_result) => conn_result.map(|_| ()),
-            Err(elapsed) => Err(IoError::new(IoErrorKind::TimedOut, elapsed)),
-        };
+        let mut duration = ProbeDuration::default();
+        let conn_result = Self::connect(endpoint, parameters, &connector, &mut duration).await;
         // Drop the connection here to make the interceptor's reference count decrease to 1
         drop(connector);
 
         // The interceptor shall not be copied by `Arc::make_mut()` since the reference count should be 1
         // Using `Arc::make_mut()` instead of `Arc::get_mut()` is just to simplify the codes
         let interceptor_inner = Arc::make_mut(&mut interceptor);
-        let Some(certificates) = interceptor_inner.get_certificates() else {
-            // Didn't get certificates, might be connection error
-            if let Err(err) = conn_result {
-                return Err(err.into());
-            } else {
-                return Err(ErrorReason::Unknown.into());
-            }
-        };
+        let certificates = interceptor_inner.get_certificates().unwrap_or_default();
+        let verification = interceptor_inner.get_verification();
+        let ocsp_response = interceptor_inner.get_ocsp_response();
 
-        let parsed_certs = certificates
+        let parsed_certs: Vec<ParsedCertificate> = certificates
             .into_iter()
             .map(X509Certificate::from_der)
             .map(|cert| cert.map(ParsedCertificate))
             .collect::<Result<_, _>>()?;
 
+        let ocsp = ocsp_response.as_ref().and_then(|der| {
+            let leaf = parsed_certs.first()?;
+            OcspResponse::parse(der, leaf, parsed_certs.get(1))
+                .map_err(|e| warn!("Failed to parse the OCSP response of {}: {:#}", endpoint, e))
+                .ok()
+        });
+
+        let ct_compliant = parameters.ct_policy.as_ref().and_then(|policy| {
+            let leaf = parsed_certs.first()?;
+            Some(policy.is_compliant(leaf, parsed_certs.get(1)))
+        });
+
+        let (probe_result, connection) = match conn_result {
+            // Didn't get certificates without any error, which shouldn't happen
+            Ok(_) if parsed_certs.is_empty() => (Err(ErrorReason::Unknown.into()), None),
+            Ok(connection) => (Ok(()), Some(connection)),
+            Err(e) => (Err(e), None),
+        };
+        let probe_result = probe_result.map_err(|e| ProbeError::from_error(&e));
+
+        // Only scan the servers which could be connected, a failed handshake may be caused by
+        // accepting the legacy versions only
+        let reachable = match &probe_result {
+            Ok(()) => true,
+            Err(e) => !matches!(
+                e.class,
+                ErrorClass::Resolve
+                    | ErrorClass::Refused
+                    | ErrorClass::Timeout
+                    | ErrorClass::StartTls
+            ),
+        };
+        let scan = if parameters.scan && reachable {
+            Some(scanner::scan(endpoint, parameters, scan_deadline).await)
+        } else {
+            None
+        };
+
         Ok(ProbeResult {
             endpoint: endpoint.clone(),
             certificates: parsed_certs,
-            probe_result: conn_result.map_err(|e| e.to_string()),
+            probe_result,
+            verification,
+            ocsp_response,
+            ocsp,
+            crls: parameters.crls.clone(),
+            ct_compliant,
+            connection,
+            scan,
+            duration,
         })
     }
+
+    /// Connect to the endpoint and finish the TLS handshake, recording the time of each phase
+    async fn connect(
+        endpoint: &Endpoint,
+        parameters: &ConnectionParameters,
+        connector: &TlsConnector,
+        duration: &mut ProbeDuration,
+    ) -> AnyResult<ConnectionInfo> {
+        let timeout_duration = parameters.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+        // Record the time of each phase even if it failed
+        let connect_start = Instant::now();
+        let stream = timeout(timeout_duration, TcpStream::connect(&endpoint.sockaddr)).await;
+        duration.connect = connect_start.elapsed();
+        let mut stream =
+            stream.map_err(|elapsed| IoError::new(IoErrorKind::TimedOut, elapsed))??;
+
+        // The STARTTLS negotiation and the TLS handshake share the same deadline
+        let handshake_start = Instant::now();
+        let handshake = timeout(timeout_duration, async {
+            if let Some(starttls) = parameters.starttls {
+                starttls
+                    .negotiate(&mut stream, &endpoint.server_name)
+                    .await?;
+            }
+
+            let tls_stream = connector
+                .connect(endpoint.server_name.clone(), stream)
+                .await?;
+            let (_, conn) = tls_stream.get_ref();
+            Ok::<_, anyhow::Error>(ConnectionInfo::from_connection(conn))
+        })
+        .await;
+        duration.handshake = handshake_start.elapsed();
+
+        handshake.map_err(|elapsed| IoError::new(IoErrorKind::TimedOut, elapsed))?
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ProbeResult {
     pub endpoint: Endpoint,
     pub certificates: Vec<ParsedCertificate>,
-    pub probe_result: Result<(), String>,
+    pub probe_result: Result<(), ProbeError>,
+    /// The result of the chain verification, if the handshake got the certificates
+    pub verification: Option<Result<(), VerifyReason>>,
+    /// The OCSP response stapled in the handshake
+    pub ocsp_response: Option<Vec<u8>>,
+    /// The stapled OCSP response about the leaf certificate, if it could be parsed
+    pub ocsp: Option<OcspResponse>,
+    /// The revocation lists which the certificates are checked against
+    pub crls: Vec<Arc<CertificateRevocationList>>,
+    /// Whether the leaf certificate complies with the CT policy, if it's configured
+    pub ct_compliant: Option<bool>,
+    /// The parameters negotiated in the handshake, if it succeeded
+    pub connection: Option<ConnectionInfo>,
+    /// The accepted protocol versions and cipher suites, if scanning is enabled
+    pub scan: Option<Vec<ScanResult>>,
+    pub duration: ProbeDuration,
+}
+
+/// The parameters negotiated with the server.
+///
+/// The key exchange group is not included, since rustls 0.22 doesn't expose it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub version: String,
+    pub cipher_suite: String,
+    pub alpn_protocol: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn from_connection(conn: &CommonState) -> Self {
+        let version = conn
+            .protocol_version()
+            .map(protocol_version_name)
+            .unwrap_or_default();
+        let cipher_suite = conn
+            .negotiated_cipher_suite()
+            .map(|suite| format!("{:?}", suite.suite()))
+            .unwrap_or_default();
+        let alpn_protocol = conn
+            .alpn_protocol()
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned());
+
+        Self {
+            version,
+            cipher_suite,
+            alpn_protocol,
+        }
+    }
+}
+
+pub fn protocol_version_name(version: ProtocolVersion) -> String {
+    match version {
+        ProtocolVersion::SSLv3 => "SSL 3.0".to_owned(),
+        ProtocolVersion::TLSv1_0 => "TLS 1.0".to_owned(),
+        ProtocolVersion::TLSv1_1 => "TLS 1.1".to_owned(),
+        ProtocolVersion::TLSv1_2 => "TLS 1.2".to_owned(),
+        ProtocolVersion::TLSv1_3 => "TLS 1.3".to_owned(),
+        version => format!("{:?}", version),
+    }
+}
+
+/// The time spent in each phase of a probe
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProbeDuration {
+    pub resolve: Duration,
+    pub connect: Duration,
+    /// Including the STARTTLS negotiation if any
+    pub handshake: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProbeError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl ProbeError {
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        Self {
+            class: ErrorClass::classify(err),
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+impl Display for ProbeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.class, &self.message)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::store::Target;
+    use crate::{starttls::StartTls, store::Target, testing::TlsServer};
     use hickory_resolver::TokioAsyncResolver;
-    use std::str::FromStr;
+    use std::{net::SocketAddr, str::FromStr};
+
+    fn endpoint(addr: SocketAddr) -> Endpoint {
+        Endpoint {
+            sockaddr: addr,
+            server_name: "localhost".try_into().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn starttls_shares_the_timeout() {
+        // Each step finishes within the timeout, but not both of them
+        let server = TlsServer {
+            smtp_delay: Some(Duration::from_millis(600)),
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let mut parameters = ConnectionParameters {
+            timeout: Some(Duration::from_secs(1)),
+            starttls: Some(StartTls::Smtp),
+            ..Default::default()
+        };
+        parameters.trusted_anchors.add(server.cert).unwrap();
+
+        let result = Prober::probe_endpoint(&endpoint(server.addr), &parameters)
+            .await
+            .unwrap();
+        assert_eq!(result.probe_result.unwrap_err().class, ErrorClass::Timeout);
+    }
+
+    #[tokio::test]
+    async fn negotiated_connection_info() {
+        for (server_protocols, alpn_protocol) in [
+            (vec![b"h2".to_vec(), b"http/1.1".to_vec()], Some("http/1.1")),
+            (Vec::new(), None),
+        ] {
+            let server = TlsServer {
+                alpn_protocols: server_protocols,
+                ..Default::default()
+            }
+            .start()
+            .await;
+            let mut parameters = ConnectionParameters {
+                insecure_skip_verify: true,
+                alpn_protocols: vec![b"http/1.1".to_vec()],
+                ..Default::default()
+            };
+            parameters.trusted_anchors.add(server.cert).unwrap();
+
+            let result = Prober::probe_endpoint(&endpoint(server.addr), &parameters)
+                .await
+                .unwrap();
+            result.probe_result.unwrap();
+            let connection = result.connection.unwrap();
+            assert_eq!(connection.version, "TLS 1.3");
+            assert!(
+                connection.cipher_suite.starts_with("TLS13_"),
+                "unexpected cipher suite: {}",
+                connection.cipher_suite
+            );
+            assert_eq!(connection.alpn_protocol.as_deref(), alpn_protocol);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_within_the_timeout() {
+        let server = TlsServer::default().start().await;
+        let mut parameters = ConnectionParameters {
+            timeout: Some(Duration::from_secs(1)),
+            insecure_skip_verify: true,
+            scan: true,
+            ..Default::default()
+        };
+        parameters.trusted_anchors.add(server.cert).unwrap();
+
+        let start = Instant::now();
+        let result = Prober::probe_endpoint(&endpoint(server.addr), &parameters)
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        // The certificates are kept, while the handshakes without answers are left out
+        result.probe_result.unwrap();
+        assert_eq!(result.certificates.len(), 1);
+        assert_eq!(result.scan, Some(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn override_server_name() {
+        // The certificate is only valid for the configured name, not for the address
+        let server = TlsServer {
+            subject_alt_names: vec!["internal.example"],
+            handshakes: 2,
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let resolver = Arc::new(TokioAsyncResolver::tokio_from_system_conf().unwrap());
+        let prober = Prober::new(resolver, ConnectionParameters::default());
+        let target = Target::from_str(&server.addr.to_string()).unwrap();
+        let mut parameters = ConnectionParameters {
+            server_names: vec!["internal.example".try_into().unwrap()],
+            ..Default::default()
+        };
+        parameters.trusted_anchors.add(server.cert).unwrap();
+
+        let results = prober.probe(&target, &parameters).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].endpoint.server_name.to_str(), "internal.example");
+        results[0].probe_result.as_ref().unwrap();
+        assert_eq!(results[0].verification, Some(Ok(())));
+
+        // No SNI is sent for an address
+        parameters.server_names.clear();
+        let results = prober.probe(&target, &parameters).await.unwrap();
+        assert_eq!(
+            results[0].probe_result.as_ref().unwrap_err().class,
+            ErrorClass::NameMismatch
+        );
+
+        assert_eq!(
+            *server.server_names.lock().unwrap(),
+            [Some("internal.example".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_each_server_name() {
+        let server = TlsServer {
+            subject_alt_names: vec!["a.example", "b.example"],
+            handshakes: 2,
+            ..Default::default()
+        }
+        .start()
+        .await;
+        let resolver = Arc::new(TokioAsyncResolver::tokio_from_system_conf().unwrap());
+        let prober = Prober::new(resolver, ConnectionParameters::default());
+        let target = Target::from_str(&server.addr.to_string()).unwrap();
+        let mut parameters = ConnectionParameters {
+            server_names: vec![
+                "a.example".try_into().unwrap(),
+                "b.example".try_into().unwrap(),
+            ],
+            ..Default::default()
+        };
+        parameters.trusted_anchors.add(server.cert).unwrap();
+
+        let results = prober.probe(&target, &parameters).await.unwrap();
+        let mut endpoints: Vec<(SocketAddr, String)> = results
+            .iter()
+            .map(|result| {
+                result.probe_result.as_ref().unwrap();
+                (
+                    result.endpoint.sockaddr,
+                    result.endpoint.server_name.to_str().into_owned(),
+                )
+            })
+            .collect();
+        endpoints.sort();
+        assert_eq!(
+            endpoints,
+            [
+                (server.addr, "a.example".to_owned()),
+                (server.addr, "b.example".to_owned())
+            ]
+        );
+        // The server completes the handshake after the client
+        let server_names = async {
+            loop {
+                let mut server_names = server.server_names.lock().unwrap().clone();
+                if server_names.len() == 2 {
+                    server_names.sort();
+                    break server_names;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let server_names = timeout(Duration::from_secs(1), server_names).await.unwrap();
+        assert_eq!(
+            server_names,
+            [Some("a.example".to_owned()), Some("b.example".to_owned())]
+        );
+    }
 
     #[tokio::test]
     async fn probe_rust_lang_org() {
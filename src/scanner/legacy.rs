@@ -0,0 +1,221 @@
+//! Detect the protocol versions which rustls doesn't implement.
+//!
+//! A ClientHello offering only the requested version is sent, and the server accepts it if it
+//! replies with a ServerHello of the same version. The handshake is never completed.
+
+use super::connect;
+use crate::{
+    configs::{ConnectionParameters, DEFAULT_TIMEOUT},
+    store::Endpoint,
+};
+use anyhow::Result as AnyResult;
+use rustls_pki_types::ServerName;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use tokio_rustls::rustls::{CipherSuite, ProtocolVersion};
+
+pub const VERSIONS: [ProtocolVersion; 3] = [
+    ProtocolVersion::SSLv3,
+    ProtocolVersion::TLSv1_0,
+    ProtocolVersion::TLSv1_1,
+];
+
+/// Commonly deployed cipher suites of the legacy versions
+const CIPHER_SUITES: [u16; 16] = [
+    0xc014, // TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA
+    0xc013, // TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA
+    0xc00a, // TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA
+    0xc009, // TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA
+    0xc012, // TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA
+    0xc011, // TLS_ECDHE_RSA_WITH_RC4_128_SHA
+    0x0039, // TLS_DHE_RSA_WITH_AES_256_CBC_SHA
+    0x0033, // TLS_DHE_RSA_WITH_AES_128_CBC_SHA
+    0x0016, // TLS_DHE_RSA_WITH_3DES_EDE_CBC_SHA
+    0x0035, // TLS_RSA_WITH_AES_256_CBC_SHA
+    0x002f, // TLS_RSA_WITH_AES_128_CBC_SHA
+    0x000a, // TLS_RSA_WITH_3DES_EDE_CBC_SHA
+    0x0005, // TLS_RSA_WITH_RC4_128_SHA
+    0x0004, // TLS_RSA_WITH_RC4_128_MD5
+    0x0009, // TLS_RSA_WITH_DES_CBC_SHA
+    0x00ff, // TLS_EMPTY_RENEGOTIATION_INFO_SCSV
+];
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+/// secp256r1, secp384r1 and x25519
+const SUPPORTED_GROUPS: [u16; 3] = [0x0017, 0x0018, 0x001d];
+/// The maximum length of a TLS record
+const MAX_RECORD_LENGTH: usize = 16384 + 2048;
+
+/// Return the cipher suite selected by the server if it accepts the version.
+pub async fn try_version(
+    endpoint: &Endpoint,
+    parameters: &ConnectionParameters,
+    version: ProtocolVersion,
+) -> AnyResult<Option<CipherSuite>> {
+    let mut stream = connect(endpoint, parameters).await?;
+    let client_hello = client_hello(version, &endpoint.server_name);
+
+    timeout(parameters.timeout.unwrap_or(DEFAULT_TIMEOUT), async {
+        stream.write_all(&client_hello).await?;
+
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await?;
+        if header[0] != CONTENT_TYPE_HANDSHAKE {
+            // Most likely an alert
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if length > MAX_RECORD_LENGTH {
+            return Ok(None);
+        }
+        let mut record = vec![0u8; length];
+        stream.read_exact(&mut record).await?;
+
+        Ok(parse_server_hello(&record)
+            .filter(|(server_version, _)| *server_version == version)
+            .map(|(_, cipher_suite)| cipher_suite))
+    })
+    .await?
+}
+
+fn client_hello(version: ProtocolVersion, server_name: &ServerName<'_>) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend(version.get_u16().to_be_bytes());
+    body.extend(random());
+    // Session ID
+    body.push(0);
+    push_u16(&mut body, (CIPHER_SUITES.len() * 2) as u16);
+    for suite in CIPHER_SUITES {
+        push_u16(&mut body, suite);
+    }
+    // Only the null compression method
+    body.extend([1, 0]);
+
+    // SSL 3.0 doesn't have extensions
+    if version != ProtocolVersion::SSLv3 {
+        let mut extensions = Vec::new();
+        if let ServerName::DnsName(name) = server_name {
+            let name = name.as_ref().as_bytes();
+            push_u16(&mut extensions, EXTENSION_SERVER_NAME);
+            push_u16(&mut extensions, (name.len() + 5) as u16);
+            push_u16(&mut extensions, (name.len() + 3) as u16);
+            // host_name
+            extensions.push(0);
+            push_u16(&mut extensions, name.len() as u16);
+            extensions.extend(name);
+        }
+        push_u16(&mut extensions, EXTENSION_SUPPORTED_GROUPS);
+        push_u16(&mut extensions, (SUPPORTED_GROUPS.len() * 2 + 2) as u16);
+        push_u16(&mut extensions, (SUPPORTED_GROUPS.len() * 2) as u16);
+        for group in SUPPORTED_GROUPS {
+            push_u16(&mut extensions, group);
+        }
+        // Only the uncompressed point format
+        push_u16(&mut extensions, EXTENSION_EC_POINT_FORMATS);
+        extensions.extend([0, 2, 1, 0]);
+
+        push_u16(&mut body, extensions.len() as u16);
+        body.extend(extensions);
+    }
+
+    let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+    handshake.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend(body);
+
+    let mut record = vec![CONTENT_TYPE_HANDSHAKE];
+    // Some servers reject a record version higher than TLS 1.0 in the ClientHello
+    let record_version = if version == ProtocolVersion::SSLv3 {
+        ProtocolVersion::SSLv3
+    } else {
+        ProtocolVersion::TLSv1_0
+    };
+    push_u16(&mut record, record_version.get_u16());
+    push_u16(&mut record, handshake.len() as u16);
+    record.extend(handshake);
+    record
+}
+
+/// Parse the version and the cipher suite of a ServerHello
+fn parse_server_hello(record: &[u8]) -> Option<(ProtocolVersion, CipherSuite)> {
+    let (&handshake_type, rest) = record.split_first()?;
+    if handshake_type != HANDSHAKE_SERVER_HELLO {
+        return None;
+    }
+    // Skip the length of the handshake message
+    let rest = rest.get(3..)?;
+    let version = ProtocolVersion::from(u16::from_be_bytes([*rest.first()?, *rest.get(1)?]));
+    // Skip the random
+    let rest = rest.get(2 + 32..)?;
+    let (&session_id_length, rest) = rest.split_first()?;
+    let rest = rest.get(session_id_length as usize..)?;
+    let cipher_suite = CipherSuite::from(u16::from_be_bytes([*rest.first()?, *rest.get(1)?]));
+
+    Some((version, cipher_suite))
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend(value.to_be_bytes());
+}
+
+/// The random doesn't need to be secure since the handshake is never completed
+fn random() -> [u8; 32] {
+    let mut random = [0u8; 32];
+    for chunk in random.chunks_mut(8) {
+        let value = RandomState::new().build_hasher().finish();
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    random
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_client_hello() {
+        let server_name = ServerName::try_from("example.com").unwrap();
+        let hello = client_hello(ProtocolVersion::TLSv1_1, &server_name);
+
+        assert_eq!(&hello[..3], [CONTENT_TYPE_HANDSHAKE, 3, 1]);
+        assert_eq!(
+            u16::from_be_bytes([hello[3], hello[4]]) as usize,
+            hello.len() - 5
+        );
+        assert_eq!(hello[5], HANDSHAKE_CLIENT_HELLO);
+        assert_eq!(&hello[9..11], [3, 2]);
+        assert!(hello.windows(11).any(|window| window == b"example.com"));
+
+        let hello = client_hello(ProtocolVersion::SSLv3, &server_name);
+        assert_eq!(&hello[1..3], [3, 0]);
+        assert!(!hello.windows(11).any(|window| window == b"example.com"));
+    }
+
+    #[test]
+    fn parse_hello() {
+        let mut record = vec![HANDSHAKE_SERVER_HELLO, 0, 0, 0x46, 3, 1];
+        record.extend([0; 32]);
+        record.extend([32]);
+        record.extend([0xaa; 32]);
+        record.extend([0x00, 0x2f, 0]);
+
+        assert_eq!(
+            parse_server_hello(&record),
+            Some((
+                ProtocolVersion::TLSv1_0,
+                CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA
+            ))
+        );
+        assert_eq!(parse_server_hello(&record[..40]), None);
+    }
+}
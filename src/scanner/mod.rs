@@ -0,0 +1,149 @@
+//! Enumerate the protocol versions and cipher suites accepted by a server.
+//!
+//! The versions supported by rustls are scanned with one handshake per cipher suite,
+//! while the legacy versions are detected with a hand-built ClientHello.
+
+use crate::{
+    configs::{ConnectionParameters, DEFAULT_TIMEOUT},
+    prober::protocol_version_name,
+    store::Endpoint,
+};
+use anyhow::Result as AnyResult;
+use futures::future::{join, join_all};
+use std::sync::Arc;
+use tokio::{
+    net::TcpStream,
+    time::{timeout, timeout_at, Instant},
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring::default_provider, CryptoProvider},
+        SupportedCipherSuite,
+    },
+    TlsConnector,
+};
+
+mod legacy;
+
+/// Whether the server accepted a protocol version and cipher suite
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanResult {
+    pub version: String,
+    pub cipher_suite: String,
+    pub supported: bool,
+}
+
+/// Try every cipher suite of rustls and the legacy protocol versions.
+///
+/// The handshakes are made concurrently and given up at the deadline, so that the scan doesn't
+/// delay the probe. The versions and cipher suites which got no answer in time are left out.
+pub async fn scan(
+    endpoint: &Endpoint,
+    parameters: &ConnectionParameters,
+    deadline: Instant,
+) -> Vec<ScanResult> {
+    // Only the negotiation matters, the certificates are checked by the probe itself
+    let parameters = ConnectionParameters {
+        insecure_skip_verify: true,
+        ..parameters.clone()
+    };
+    let parameters = &parameters;
+
+    let suites = default_provider()
+        .cipher_suites
+        .into_iter()
+        .map(|suite| async move {
+            let supported =
+                match timeout_at(deadline, try_cipher_suite(endpoint, parameters, suite)).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        debug!(
+                            "{} rejected {:?}: {:#}",
+                            &endpoint.sockaddr,
+                            suite.suite(),
+                            e
+                        );
+                        false
+                    }
+                    Err(_) => {
+                        debug!(
+                            "{} didn't answer {:?} in time",
+                            &endpoint.sockaddr,
+                            suite.suite()
+                        );
+                        return None;
+                    }
+                };
+            Some(ScanResult {
+                version: protocol_version_name(suite.version().version),
+                cipher_suite: format!("{:?}", suite.suite()),
+                supported,
+            })
+        });
+
+    let legacy_versions = legacy::VERSIONS.into_iter().map(|version| async move {
+        let cipher_suite =
+            match timeout_at(deadline, legacy::try_version(endpoint, parameters, version)).await {
+                Ok(Ok(cipher_suite)) => cipher_suite,
+                Ok(Err(e)) => {
+                    debug!("{} rejected {:?}: {:#}", &endpoint.sockaddr, version, e);
+                    None
+                }
+                Err(_) => {
+                    debug!("{} didn't answer {:?} in time", &endpoint.sockaddr, version);
+                    return None;
+                }
+            };
+        Some(ScanResult {
+            version: protocol_version_name(version),
+            cipher_suite: cipher_suite
+                .map(|suite| format!("{:?}", suite))
+                .unwrap_or_default(),
+            supported: cipher_suite.is_some(),
+        })
+    });
+
+    let (suites, legacy_versions) = join(join_all(suites), join_all(legacy_versions)).await;
+    suites
+        .into_iter()
+        .chain(legacy_versions)
+        .flatten()
+        .collect()
+}
+
+async fn try_cipher_suite(
+    endpoint: &Endpoint,
+    parameters: &ConnectionParameters,
+    suite: SupportedCipherSuite,
+) -> AnyResult<()> {
+    let provider = CryptoProvider {
+        cipher_suites: vec![suite],
+        ..default_provider()
+    };
+    let (tls_config, _) =
+        parameters.build_tls_config_with(Arc::new(provider), &[suite.version()])?;
+    let connector = TlsConnector::from(Arc::new(tls_config));
+
+    let stream = connect(endpoint, parameters).await?;
+    timeout(
+        parameters.timeout.unwrap_or(DEFAULT_TIMEOUT),
+        connector.connect(endpoint.server_name.clone(), stream),
+    )
+    .await??;
+    Ok(())
+}
+
+/// Connect to the endpoint and negotiate STARTTLS if needed.
+async fn connect(endpoint: &Endpoint, parameters: &ConnectionParameters) -> AnyResult<TcpStream> {
+    let timeout_duration = parameters.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+    let mut stream = timeout(timeout_duration, TcpStream::connect(&endpoint.sockaddr)).await??;
+    if let Some(starttls) = parameters.starttls {
+        timeout(
+            timeout_duration,
+            starttls.negotiate(&mut stream, &endpoint.server_name),
+        )
+        .await??;
+    }
+    Ok(stream)
+}
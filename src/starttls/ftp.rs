@@ -0,0 +1,35 @@
+use super::{negotiation_error, read_reply, write_line};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut code, mut lines) = read_reply(stream).await?;
+    // 120: Service ready in nnn minutes
+    while code == 120 {
+        (code, lines) = read_reply(stream).await?;
+    }
+    if code != 220 {
+        return Err(negotiation_error(format!(
+            "unexpected greeting: {} {}",
+            code,
+            lines.join(" ")
+        ))
+        .into());
+    }
+
+    // RFC 4217 Section 4
+    write_line(stream, "AUTH TLS").await?;
+    let (code, lines) = read_reply(stream).await?;
+    match code {
+        234 => Ok(()),
+        // Command not implemented, parameter not implemented, or refused by policy
+        502 | 504 | 534 => Err(ErrorReason::StartTlsUnsupported.into()),
+        _ => Err(
+            negotiation_error(format!("AUTH TLS rejected: {} {}", code, lines.join(" "))).into(),
+        ),
+    }
+}
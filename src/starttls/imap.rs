@@ -0,0 +1,48 @@
+use super::{negotiation_error, read_line, write_line};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let greeting = read_line(stream).await?;
+    if !greeting.starts_with("* OK") {
+        return Err(negotiation_error(format!("unexpected greeting: {}", greeting)).into());
+    }
+
+    let capabilities = command(stream, "a001", "CAPABILITY").await?;
+    let has_starttls = capabilities
+        .iter()
+        .filter_map(|line| line.strip_prefix("* CAPABILITY "))
+        .flat_map(|caps| caps.split_ascii_whitespace())
+        .any(|cap| cap.eq_ignore_ascii_case("STARTTLS"));
+    if !has_starttls {
+        return Err(ErrorReason::StartTlsUnsupported.into());
+    }
+
+    command(stream, "a002", "STARTTLS").await?;
+    Ok(())
+}
+
+/// Send a tagged command and return the untagged responses if it completed with `OK`
+async fn command<S>(stream: &mut S, tag: &str, command: &str) -> AnyResult<Vec<String>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_line(stream, &format!("{} {}", tag, command)).await?;
+
+    let mut untagged = Vec::new();
+    loop {
+        let line = read_line(stream).await?;
+        if let Some(status) = line.strip_prefix(tag).and_then(|s| s.strip_prefix(' ')) {
+            if status.starts_with("OK") {
+                return Ok(untagged);
+            } else {
+                return Err(negotiation_error(format!("{} rejected: {}", command, status)).into());
+            }
+        }
+        untagged.push(line);
+    }
+}
@@ -0,0 +1,137 @@
+use super::negotiation_error;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The response of StartTLS is small, so refuse anything unreasonably large
+const MAX_MESSAGE_LENGTH: usize = 65536;
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_OCTET_STRING: u8 = 0x04;
+/// `[APPLICATION 24]` ExtendedResponse
+const TAG_EXTENDED_RESPONSE: u8 = 0x78;
+
+/// `resultCode` of ExtendedResponse
+const RESULT_SUCCESS: u32 = 0;
+const RESULT_PROTOCOL_ERROR: u32 = 2;
+const RESULT_UNAVAILABLE: u32 = 52;
+
+/// LDAPMessage carrying an ExtendedRequest with the StartTLS OID
+/// `1.3.6.1.4.1.1466.20037` (RFC 4511 Section 4.14.1)
+pub(super) const START_TLS_REQUEST: &[u8] = &[
+    0x30, 0x1d, // LDAPMessage: SEQUENCE
+    0x02, 0x01, 0x01, // messageID: INTEGER 1
+    0x77, 0x18, // protocolOp: [APPLICATION 23] ExtendedRequest
+    0x80, 0x16, // requestName: [0] LDAPOID
+    b'1', b'.', b'3', b'.', b'6', b'.', b'1', b'.', b'4', b'.', b'1', b'.', b'1', b'4', b'6', b'6',
+    b'.', b'2', b'0', b'0', b'3', b'7',
+];
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(START_TLS_REQUEST).await?;
+    stream.flush().await?;
+
+    let message = read_message(stream).await?;
+    let mut reader = BerReader(&message);
+
+    let message_id = reader.expect(TAG_INTEGER)?;
+    if message_id != [0x01] {
+        return Err(negotiation_error("unexpected message ID").into());
+    }
+
+    let mut response = BerReader(reader.expect(TAG_EXTENDED_RESPONSE)?);
+    let result_code = response
+        .expect(TAG_ENUMERATED)?
+        .iter()
+        .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+    let _matched_dn = response.expect(TAG_OCTET_STRING)?;
+    let diagnostic = String::from_utf8_lossy(response.expect(TAG_OCTET_STRING)?);
+
+    match result_code {
+        RESULT_SUCCESS => Ok(()),
+        RESULT_PROTOCOL_ERROR | RESULT_UNAVAILABLE => Err(ErrorReason::StartTlsUnsupported.into()),
+        code => Err(negotiation_error(format!(
+            "StartTLS rejected: result code {} {}",
+            code, diagnostic
+        ))
+        .into()),
+    }
+}
+
+/// Read a whole LDAPMessage and return the content of the outer SEQUENCE
+async fn read_message<S>(stream: &mut S) -> AnyResult<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let tag = stream.read_u8().await?;
+    if tag != TAG_SEQUENCE {
+        return Err(negotiation_error(format!("unexpected tag: {:#04x}", tag)).into());
+    }
+
+    let first = stream.read_u8().await?;
+    let length = if first & 0x80 == 0 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > std::mem::size_of::<u32>() {
+            return Err(negotiation_error("unsupported length encoding").into());
+        }
+        let mut length = 0usize;
+        for _ in 0..count {
+            length = (length << 8) | usize::from(stream.read_u8().await?);
+        }
+        length
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(negotiation_error("message too long").into());
+    }
+
+    let mut content = vec![0u8; length];
+    stream.read_exact(&mut content).await?;
+    Ok(content)
+}
+
+/// A minimal reader of definite-length BER elements
+struct BerReader<'a>(&'a [u8]);
+
+impl<'a> BerReader<'a> {
+    /// Read the next element, and return its content if it has the expected tag
+    fn expect(&mut self, expected: u8) -> AnyResult<&'a [u8]> {
+        let (&tag, rest) = self
+            .0
+            .split_first()
+            .ok_or_else(|| negotiation_error("truncated message"))?;
+        if tag != expected {
+            return Err(negotiation_error(format!("unexpected tag: {:#04x}", tag)).into());
+        }
+
+        let (&first, mut rest) = rest
+            .split_first()
+            .ok_or_else(|| negotiation_error("truncated message"))?;
+        let length = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            let count = usize::from(first & 0x7f);
+            if count == 0 || count > std::mem::size_of::<u32>() || rest.len() < count {
+                return Err(negotiation_error("unsupported length encoding").into());
+            }
+            let (bytes, remain) = rest.split_at(count);
+            rest = remain;
+            bytes
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | usize::from(*b))
+        };
+        if rest.len() < length {
+            return Err(negotiation_error("truncated message").into());
+        }
+
+        let (content, rest) = rest.split_at(length);
+        self.0 = rest;
+        Ok(content)
+    }
+}
@@ -0,0 +1,580 @@
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use rustls_pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+mod ftp;
+mod imap;
+mod ldap;
+mod mysql;
+mod nntp;
+mod pop3;
+mod postgres;
+mod smtp;
+mod xmpp;
+
+/// Lines longer than this are considered as protocol errors
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// The plaintext protocol to speak before upgrading the connection to TLS
+///
+/// Besides the STARTTLS-like commands, this also covers database protocols which
+/// negotiate TLS with their own binary messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartTls {
+    Smtp,
+    Imap,
+    Pop3,
+    Ldap,
+    Ftp,
+    Xmpp,
+    Nntp,
+    Postgres,
+    Mysql,
+}
+
+impl StartTls {
+    /// Run the protocol-specific preamble on the stream.
+    ///
+    /// When this returns `Ok`, the server is waiting for the TLS ClientHello.
+    pub async fn negotiate<S>(&self, stream: &mut S, server_name: &ServerName<'_>) -> AnyResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self {
+            StartTls::Smtp => smtp::negotiate(stream).await,
+            StartTls::Imap => imap::negotiate(stream).await,
+            StartTls::Pop3 => pop3::negotiate(stream).await,
+            StartTls::Ldap => ldap::negotiate(stream).await,
+            StartTls::Ftp => ftp::negotiate(stream).await,
+            StartTls::Xmpp => xmpp::negotiate(stream, server_name).await,
+            StartTls::Nntp => nntp::negotiate(stream).await,
+            StartTls::Postgres => postgres::negotiate(stream).await,
+            StartTls::Mysql => mysql::negotiate(stream).await,
+        }
+    }
+}
+
+/// Read a line without the trailing CRLF.
+///
+/// The stream is read byte by byte, so that nothing after the line is consumed
+/// before the TLS handshake starts.
+async fn read_line<S>(stream: &mut S) -> AnyResult<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    loop {
+        let byte = match stream.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(negotiation_error("connection closed by the server").into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if byte == b'\n' {
+            break;
+        }
+        if line.len() >= MAX_LINE_LENGTH {
+            return Err(negotiation_error("line too long").into());
+        }
+        line.push(byte);
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    let line = String::from_utf8_lossy(&line).into_owned();
+    trace!("STARTTLS <- {}", &line);
+    Ok(line)
+}
+
+async fn write_line<S>(stream: &mut S, line: &str) -> AnyResult<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    trace!("STARTTLS -> {}", line);
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a reply in the SMTP style, which is a three digit code optionally spanning
+/// several lines marked by a `-` after the code.
+///
+/// Return the code and the text of each line.
+async fn read_reply<S>(stream: &mut S) -> AnyResult<(u16, Vec<String>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let first = read_line(stream).await?;
+    let code = parse_reply_code(&first)?;
+    let code_str = &first[..3];
+
+    let mut multiline = first.as_bytes().get(3) == Some(&b'-');
+    let mut lines = vec![first.get(4..).unwrap_or_default().to_owned()];
+    while multiline {
+        let line = read_line(stream).await?;
+        // Only the final line is required to start with the reply code
+        let text = match line.strip_prefix(code_str) {
+            Some(rest) if rest.is_empty() || rest.starts_with(' ') => {
+                multiline = false;
+                rest.get(1..).unwrap_or_default()
+            }
+            Some(rest) if rest.starts_with('-') => &rest[1..],
+            _ => line.as_str(),
+        };
+        lines.push(text.to_owned());
+    }
+
+    Ok((code, lines))
+}
+
+/// Read lines until the terminating `.` line, as used by POP3 and NNTP
+async fn read_dot_terminated<S>(stream: &mut S) -> AnyResult<Vec<String>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut lines = Vec::new();
+    loop {
+        let line = read_line(stream).await?;
+        if line == "." {
+            return Ok(lines);
+        }
+        lines.push(line);
+    }
+}
+
+fn parse_reply_code(line: &str) -> AnyResult<u16> {
+    line.get(..3)
+        .filter(|code| code.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| negotiation_error(format!("invalid reply: {}", line)).into())
+}
+
+fn negotiation_error(msg: impl Into<String>) -> ErrorReason {
+    ErrorReason::StartTlsNegotiation(msg.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::{TcpListener, TcpStream},
+        task::JoinHandle,
+    };
+
+    enum Step {
+        /// Send the line to the client
+        Send(&'static str),
+        /// Expect the client to send a line starting with the pattern
+        Expect(&'static str),
+        /// Send the bytes as is
+        SendRaw(&'static [u8]),
+        /// Expect the client to send exactly the bytes
+        ExpectRaw(&'static [u8]),
+    }
+
+    /// Spawn a scripted plaintext server which accepts a single connection
+    async fn fake_server(script: Vec<Step>) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            for step in script {
+                match step {
+                    Step::Send(line) => {
+                        writer.write_all(line.as_bytes()).await.unwrap();
+                        writer.write_all(b"\r\n").await.unwrap();
+                    }
+                    Step::Expect(pattern) => {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).await.unwrap();
+                        assert!(
+                            line.starts_with(pattern),
+                            "expect {:?}, got {:?}",
+                            pattern,
+                            line
+                        );
+                    }
+                    Step::SendRaw(data) => {
+                        writer.write_all(data).await.unwrap();
+                    }
+                    Step::ExpectRaw(expected) => {
+                        let mut data = vec![0u8; expected.len()];
+                        reader.read_exact(&mut data).await.unwrap();
+                        assert_eq!(&data, expected);
+                    }
+                }
+            }
+        });
+
+        (addr, handle)
+    }
+
+    async fn negotiate_with(starttls: StartTls, script: Vec<Step>) -> AnyResult<()> {
+        let (addr, server) = fake_server(script).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("example.com").unwrap();
+        let result = starttls.negotiate(&mut stream, &server_name).await;
+
+        // Propagate the assertion failures in the server
+        server.await.unwrap();
+        result
+    }
+
+    fn assert_unsupported(result: AnyResult<()>) {
+        let err = result.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<ErrorReason>(),
+                Some(ErrorReason::StartTlsUnsupported)
+            ),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn smtp_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Smtp,
+            vec![
+                Send("220-mail.example.com ESMTP"),
+                Send("220 ready"),
+                Expect("EHLO "),
+                Send("250-mail.example.com"),
+                Send("250-PIPELINING"),
+                Send("250-STARTTLS"),
+                Send("250 8BITMIME"),
+                Expect("STARTTLS"),
+                Send("220 2.0.0 Ready to start TLS"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn smtp_without_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Smtp,
+            vec![
+                Send("220 mail.example.com ESMTP"),
+                Expect("EHLO "),
+                Send("250-mail.example.com"),
+                Send("250 8BITMIME"),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn smtp_rejected() {
+        use Step::*;
+        let result = negotiate_with(StartTls::Smtp, vec![Send("554 No SMTP service here")]).await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ErrorReason>(),
+            Some(ErrorReason::StartTlsNegotiation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn imap_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Imap,
+            vec![
+                Send("* OK IMAP4rev1 Service Ready"),
+                Expect("a001 CAPABILITY"),
+                Send("* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED"),
+                Send("a001 OK CAPABILITY completed"),
+                Expect("a002 STARTTLS"),
+                Send("a002 OK Begin TLS negotiation now"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn imap_without_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Imap,
+            vec![
+                Send("* OK IMAP4rev1 Service Ready"),
+                Expect("a001 CAPABILITY"),
+                Send("* CAPABILITY IMAP4rev1 AUTH=PLAIN"),
+                Send("a001 OK CAPABILITY completed"),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn pop3_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Pop3,
+            vec![
+                Send("+OK POP3 server ready"),
+                Expect("CAPA"),
+                Send("+OK Capability list follows"),
+                Send("USER"),
+                Send("STLS"),
+                Send("."),
+                Expect("STLS"),
+                Send("+OK Begin TLS negotiation"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pop3_without_capa() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Pop3,
+            vec![
+                Send("+OK POP3 server ready"),
+                Expect("CAPA"),
+                Send("-ERR unknown command"),
+                Expect("STLS"),
+                Send("-ERR command not permitted"),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn ldap_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Ldap,
+            vec![
+                ExpectRaw(ldap::START_TLS_REQUEST),
+                SendRaw(&[
+                    0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04,
+                    0x00,
+                ]),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ldap_unavailable() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Ldap,
+            vec![
+                ExpectRaw(ldap::START_TLS_REQUEST),
+                SendRaw(&[
+                    0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x34, 0x04, 0x00, 0x04,
+                    0x00,
+                ]),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn ftp_auth_tls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Ftp,
+            vec![
+                Send("220-Welcome"),
+                Send("  to the FTP server"),
+                Send("220 Ready"),
+                Expect("AUTH TLS"),
+                Send("234 AUTH TLS successful"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ftp_without_auth_tls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Ftp,
+            vec![
+                Send("220 Ready"),
+                Expect("AUTH TLS"),
+                Send("502 Command not implemented"),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn xmpp_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Xmpp,
+            vec![
+                ExpectRaw(b"<?xml version='1.0'?><stream:stream to='example.com' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>"),
+                SendRaw(b"<?xml version='1.0'?><stream:stream from='example.com' id='1' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>"),
+                SendRaw(b"<stream:features><starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'><required/></starttls></stream:features>"),
+                ExpectRaw(b"<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"),
+                SendRaw(b"<proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn xmpp_without_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Xmpp,
+            vec![
+                ExpectRaw(b"<?xml version='1.0'?><stream:stream to='example.com' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>"),
+                SendRaw(b"<?xml version='1.0'?><stream:stream from='example.com' id='1' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>"),
+                SendRaw(b"<stream:features><mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'><mechanism>PLAIN</mechanism></mechanisms></stream:features>"),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn nntp_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Nntp,
+            vec![
+                Send("200 NNTP Service Ready, posting permitted"),
+                Expect("CAPABILITIES"),
+                Send("101 Capability list:"),
+                Send("VERSION 2"),
+                Send("READER"),
+                Send("STARTTLS"),
+                Send("."),
+                Expect("STARTTLS"),
+                Send("382 Continue with TLS negotiation"),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn nntp_without_starttls() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Nntp,
+            vec![
+                Send("201 NNTP Service Ready, posting prohibited"),
+                Expect("CAPABILITIES"),
+                Send("101 Capability list:"),
+                Send("VERSION 2"),
+                Send("READER"),
+                Send("."),
+            ],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn postgres_ssl_request() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Postgres,
+            vec![ExpectRaw(postgres::SSL_REQUEST), SendRaw(b"S")],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn postgres_without_ssl() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Postgres,
+            vec![ExpectRaw(postgres::SSL_REQUEST), SendRaw(b"N")],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn mysql_ssl_request() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Mysql,
+            vec![
+                SendRaw(&[
+                    0x1b, 0x00, 0x00, 0x00, // header
+                    0x0a, b'8', b'.', b'0', b'.', b'0', 0x00, // version
+                    0x01, 0x00, 0x00, 0x00, // connection id
+                    b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', 0x00, // auth data
+                    0xff, 0xff, // capabilities
+                    0x2d, 0x02, 0x00, // character set and status
+                    0xff, 0xdf, // upper capabilities
+                ]),
+                ExpectRaw(&[
+                    0x20, 0x00, 0x00, 0x01, // header
+                    0x01, 0x8a, 0x00, 0x00, // capabilities
+                    0x00, 0x00, 0x00, 0x01, // max packet size
+                    0x2d, // character set
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ]),
+            ],
+        )
+        .await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mysql_without_ssl() {
+        use Step::*;
+        let result = negotiate_with(
+            StartTls::Mysql,
+            vec![SendRaw(&[
+                0x16, 0x00, 0x00, 0x00, // header
+                0x0a, b'8', b'.', b'0', b'.', b'0', 0x00, // version
+                0x01, 0x00, 0x00, 0x00, // connection id
+                b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', 0x00, // auth data
+                0xff, 0xf7, // capabilities
+            ])],
+        )
+        .await;
+        assert_unsupported(result);
+    }
+
+    #[tokio::test]
+    async fn mysql_packet_too_long() {
+        use Step::*;
+        let result =
+            negotiate_with(StartTls::Mysql, vec![SendRaw(&[0xff, 0xff, 0xff, 0x00])]).await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ErrorReason>(),
+            Some(ErrorReason::StartTlsNegotiation(_))
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+use super::negotiation_error;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const PROTOCOL_VERSION: u8 = 10;
+/// The first byte of an ERR_Packet
+const ERR_PACKET: u8 = 0xff;
+
+const CLIENT_LONG_PASSWORD: u32 = 0x0000_0001;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_SSL: u32 = 0x0000_0800;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+
+const MAX_PACKET_SIZE: u32 = 0x0100_0000;
+/// The initial handshake is small, so refuse anything unreasonably large
+const MAX_PACKET_LENGTH: usize = 65536;
+/// utf8mb4_general_ci
+const CHARACTER_SET: u8 = 45;
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (sequence_id, handshake) = read_packet(stream).await?;
+    let capabilities = parse_handshake(&handshake)?;
+    if capabilities & CLIENT_SSL == 0 {
+        return Err(ErrorReason::StartTlsUnsupported.into());
+    }
+
+    // Protocol::SSLRequest, sent in place of the HandshakeResponse
+    let mut packet = Vec::with_capacity(36);
+    packet.extend_from_slice(&[32, 0, 0, sequence_id.wrapping_add(1)]);
+    packet.extend_from_slice(
+        &(CLIENT_LONG_PASSWORD | CLIENT_PROTOCOL_41 | CLIENT_SSL | CLIENT_SECURE_CONNECTION)
+            .to_le_bytes(),
+    );
+    packet.extend_from_slice(&MAX_PACKET_SIZE.to_le_bytes());
+    packet.push(CHARACTER_SET);
+    packet.extend_from_slice(&[0u8; 23]);
+
+    stream.write_all(&packet).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a packet and return its sequence ID and payload
+async fn read_packet<S>(stream: &mut S) -> AnyResult<(u8, Vec<u8>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    if length > MAX_PACKET_LENGTH {
+        return Err(negotiation_error("packet too long").into());
+    }
+
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    Ok((header[3], payload))
+}
+
+/// Parse Protocol::HandshakeV10 and return the server capability flags
+fn parse_handshake(payload: &[u8]) -> AnyResult<u32> {
+    let (&version, rest) = payload
+        .split_first()
+        .ok_or_else(|| negotiation_error("empty handshake packet"))?;
+    if version == ERR_PACKET {
+        let message = rest.get(2..).unwrap_or_default();
+        return Err(negotiation_error(format!(
+            "server error: {}",
+            String::from_utf8_lossy(message)
+        ))
+        .into());
+    }
+    if version != PROTOCOL_VERSION {
+        return Err(negotiation_error(format!("unsupported protocol version: {}", version)).into());
+    }
+
+    // server version (NUL-terminated)
+    let nul = rest
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| negotiation_error("truncated handshake packet"))?;
+    // connection id (4), auth-plugin-data-part-1 (8), filler (1)
+    let rest = rest
+        .get(nul + 1 + 4 + 8 + 1..)
+        .ok_or_else(|| negotiation_error("truncated handshake packet"))?;
+
+    let lower = rest
+        .get(..2)
+        .ok_or_else(|| negotiation_error("truncated handshake packet"))?;
+    let mut capabilities = u32::from(u16::from_le_bytes([lower[0], lower[1]]));
+    // character set (1), status flags (2), then the upper 2 bytes of the capability flags
+    if let Some(upper) = rest.get(5..7) {
+        capabilities |= u32::from(u16::from_le_bytes([upper[0], upper[1]])) << 16;
+    }
+
+    Ok(capabilities)
+}
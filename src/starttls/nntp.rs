@@ -0,0 +1,46 @@
+use super::{negotiation_error, read_dot_terminated, read_reply, write_line};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (code, lines) = read_reply(stream).await?;
+    // 200: Posting allowed, 201: Posting prohibited
+    if code != 200 && code != 201 {
+        return Err(negotiation_error(format!(
+            "unexpected greeting: {} {}",
+            code,
+            lines.join(" ")
+        ))
+        .into());
+    }
+
+    // RFC 3977 Section 5.2
+    write_line(stream, "CAPABILITIES").await?;
+    let (code, _) = read_reply(stream).await?;
+    if code == 101 {
+        let capabilities = read_dot_terminated(stream).await?;
+        if !capabilities.iter().any(|cap| {
+            cap.split_ascii_whitespace()
+                .next()
+                .map_or(false, |label| label.eq_ignore_ascii_case("STARTTLS"))
+        }) {
+            return Err(ErrorReason::StartTlsUnsupported.into());
+        }
+    }
+
+    // RFC 4642 Section 2.2
+    write_line(stream, "STARTTLS").await?;
+    let (code, lines) = read_reply(stream).await?;
+    match code {
+        382 => Ok(()),
+        // Command unavailable
+        502 => Err(ErrorReason::StartTlsUnsupported.into()),
+        _ => Err(
+            negotiation_error(format!("STARTTLS rejected: {} {}", code, lines.join(" "))).into(),
+        ),
+    }
+}
@@ -0,0 +1,37 @@
+use super::{negotiation_error, read_dot_terminated, read_line, write_line};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let greeting = read_line(stream).await?;
+    if !greeting.starts_with("+OK") {
+        return Err(negotiation_error(format!("unexpected greeting: {}", greeting)).into());
+    }
+
+    // CAPA is optional (RFC 2449), so just try STLS when the server doesn't support it
+    write_line(stream, "CAPA").await?;
+    let capa = read_line(stream).await?;
+    if capa.starts_with("+OK") {
+        let capabilities = read_dot_terminated(stream).await?;
+        if !capabilities
+            .iter()
+            .any(|cap| cap.trim().eq_ignore_ascii_case("STLS"))
+        {
+            return Err(ErrorReason::StartTlsUnsupported.into());
+        }
+    }
+
+    write_line(stream, "STLS").await?;
+    let resp = read_line(stream).await?;
+    if resp.starts_with("+OK") {
+        Ok(())
+    } else if capa.starts_with("+OK") {
+        Err(negotiation_error(format!("STLS rejected: {}", resp)).into())
+    } else {
+        Err(ErrorReason::StartTlsUnsupported.into())
+    }
+}
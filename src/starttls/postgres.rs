@@ -0,0 +1,24 @@
+use super::negotiation_error;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// SSLRequest: the message length followed by the SSL request code 80877103
+/// (PostgreSQL Frontend/Backend Protocol, Section 55.2.10)
+pub(super) const SSL_REQUEST: &[u8] = &[0x00, 0x00, 0x00, 0x08, 0x04, 0xd2, 0x16, 0x2f];
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(SSL_REQUEST).await?;
+    stream.flush().await?;
+
+    match stream.read_u8().await? {
+        b'S' => Ok(()),
+        b'N' => Err(ErrorReason::StartTlsUnsupported.into()),
+        // Servers predating SSL support respond with an ErrorResponse
+        b'E' => Err(negotiation_error("the server rejected SSLRequest").into()),
+        other => Err(negotiation_error(format!("unexpected response: {:#04x}", other)).into()),
+    }
+}
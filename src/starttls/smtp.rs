@@ -0,0 +1,48 @@
+use super::{negotiation_error, read_reply, write_line};
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The name sent in the `EHLO` command
+const CLIENT_NAME: &str = "tls-certificate-exporter";
+
+pub(super) async fn negotiate<S>(stream: &mut S) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (code, lines) = read_reply(stream).await?;
+    if code != 220 {
+        return Err(negotiation_error(format!(
+            "unexpected greeting: {} {}",
+            code,
+            lines.join(" ")
+        ))
+        .into());
+    }
+
+    write_line(stream, &format!("EHLO {}", CLIENT_NAME)).await?;
+    let (code, lines) = read_reply(stream).await?;
+    if code != 250 {
+        return Err(
+            negotiation_error(format!("EHLO rejected: {} {}", code, lines.join(" "))).into(),
+        );
+    }
+    // The first line is the server's domain, the others are the extensions
+    if !lines
+        .iter()
+        .skip(1)
+        .any(|ext| ext.trim().eq_ignore_ascii_case("STARTTLS"))
+    {
+        return Err(ErrorReason::StartTlsUnsupported.into());
+    }
+
+    write_line(stream, "STARTTLS").await?;
+    let (code, lines) = read_reply(stream).await?;
+    if code != 220 {
+        return Err(
+            negotiation_error(format!("STARTTLS rejected: {} {}", code, lines.join(" "))).into(),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use super::negotiation_error;
+use crate::error::ErrorReason;
+use anyhow::Result as AnyResult;
+use rustls_pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Stream features are small, so refuse anything unreasonably large
+const MAX_STANZA_LENGTH: usize = 65536;
+
+const NS_TLS: &str = "urn:ietf:params:xml:ns:xmpp-tls";
+
+pub(super) async fn negotiate<S>(stream: &mut S, server_name: &ServerName<'_>) -> AnyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // RFC 6120 Section 4.2
+    let header = format!(
+        "<?xml version='1.0'?><stream:stream to='{}' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>",
+        server_name.to_str()
+    );
+    write(stream, &header).await?;
+
+    // The stream header and the features are read together
+    let received = read_until(stream, &["</stream:features>", "<stream:features/>"]).await?;
+    if received.contains("<stream:error") {
+        return Err(negotiation_error(format!("stream error: {}", received)).into());
+    }
+    if !received.contains("<starttls") {
+        return Err(ErrorReason::StartTlsUnsupported.into());
+    }
+
+    // RFC 6120 Section 5.4.2
+    write(stream, &format!("<starttls xmlns='{}'/>", NS_TLS)).await?;
+    let received = read_until(stream, &["<proceed", "<failure"]).await?;
+    if received.ends_with("<failure") {
+        return Err(negotiation_error("the server refused STARTTLS").into());
+    }
+    // Consume the rest of the `<proceed .../>` element
+    read_until(stream, &[">"]).await?;
+
+    Ok(())
+}
+
+async fn write<S>(stream: &mut S, data: &str) -> AnyResult<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    trace!("STARTTLS -> {}", data);
+    stream.write_all(data.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read byte by byte until any of the patterns is encountered.
+///
+/// The patterns are matched against the raw text, which is good enough for the
+/// few elements we care about.
+async fn read_until<S>(stream: &mut S, patterns: &[&str]) -> AnyResult<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::new();
+    loop {
+        let byte = match stream.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(negotiation_error("connection closed by the server").into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        buffer.push(byte);
+
+        if patterns.iter().any(|p| buffer.ends_with(p.as_bytes())) {
+            break;
+        }
+        if buffer.len() >= MAX_STANZA_LENGTH {
+            return Err(negotiation_error("stanza too long").into());
+        }
+    }
+
+    let received = String::from_utf8_lossy(&buffer).into_owned();
+    trace!("STARTTLS <- {}", &received);
+    Ok(received)
+}
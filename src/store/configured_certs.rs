@@ -0,0 +1,29 @@
+use crate::cert::CertificateIdentifier;
+
+/// Where a configured certificate comes from, which is exported as the `source` label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CertificateSource {
+    /// The global `trusted_anchors`
+    GlobalAnchor,
+    /// The `ca` of a target
+    TargetCa,
+    /// The `cert` of a target, which is sent for client authentication
+    ClientCert,
+}
+
+impl CertificateSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificateSource::GlobalAnchor => "global_anchor",
+            CertificateSource::TargetCa => "target_ca",
+            CertificateSource::ClientCert => "client_cert",
+        }
+    }
+}
+
+/// The certificates of a source, in the configured order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfiguredCertificates {
+    pub source: CertificateSource,
+    pub cert_idents: Vec<CertificateIdentifier>,
+}
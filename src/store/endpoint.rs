@@ -10,6 +10,10 @@ use std::{
 
 use super::target::Target;
 
+/// An address to connect to, together with the name used for SNI.
+///
+/// Both of them identify an endpoint, since a server may present different
+/// certificates for different names on the same address.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Endpoint {
     pub sockaddr: SocketAddr,
@@ -1,14 +1,69 @@
 use super::{Endpoint, Target};
-use crate::cert::CertificateIdentifier;
+use crate::{
+    cert::{
+        CertificateIdentifier, CertificateRevocationList, OcspResponse, ParsedCertificate,
+        SignedCertificateTimestamp, SubjectAltName,
+    },
+    error::VerifyReason,
+    prober::{ConnectionInfo, ProbeDuration, ProbeError},
+    scanner::ScanResult,
+};
 use chrono::{DateTime, Utc};
+use std::{sync::Arc, time::Duration};
+
+/// The extensions of the leaf certificate which are exported for the endpoint
+#[derive(Clone, Debug)]
+pub struct LeafDetails {
+    /// `None` if the extension is malformed
+    pub sans: Option<Vec<SubjectAltName>>,
+    /// `None` if the extension is malformed
+    pub scts: Option<Vec<SignedCertificateTimestamp>>,
+    pub hostname_match: bool,
+}
+
+impl LeafDetails {
+    pub fn parse(leaf: &ParsedCertificate, endpoint: &Endpoint) -> Self {
+        let sans = leaf
+            .subject_alt_names()
+            .map_err(|e| {
+                warn!(
+                    "Failed to parse the subject alternative names of {}: {}",
+                    endpoint, e
+                )
+            })
+            .ok();
+        let scts = leaf
+            .embedded_scts()
+            .map_err(|e| warn!("Failed to parse the embedded SCTs of {}: {}", endpoint, e))
+            .ok();
+        let hostname_match = leaf.matches_server_name(&endpoint.server_name);
+        Self {
+            sans,
+            scts,
+            hostname_match,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct EndpointState {
     pub endpoint: Endpoint,
     pub target: Option<Target>,
     pub cert_idents: Vec<CertificateIdentifier>,
-    pub probe_result: Result<(), String>,
+    /// Parsed once when the probe result is stored, rather than on every scrape
+    pub leaf: Option<LeafDetails>,
+    pub probe_result: Result<(), ProbeError>,
+    pub verification: Option<Result<(), VerifyReason>>,
+    pub ocsp_stapled: bool,
+    pub ocsp: Option<OcspResponse>,
+    pub crls: Vec<Arc<CertificateRevocationList>>,
+    pub ct_compliant: Option<bool>,
+    pub connection: Option<ConnectionInfo>,
+    pub scan: Option<Vec<ScanResult>>,
+    pub duration: ProbeDuration,
     pub last_update: Option<DateTime<Utc>>,
+    /// When the endpoint started failing, if the latest probe failed
+    pub failing_since: Option<DateTime<Utc>>,
 }
 
 impl EndpointState {
@@ -17,8 +72,18 @@ impl EndpointState {
             endpoint,
             target: None,
             cert_idents: Default::default(),
+            leaf: None,
             probe_result: Ok(()),
+            verification: None,
+            ocsp_stapled: false,
+            ocsp: None,
+            crls: Vec::new(),
+            ct_compliant: None,
+            connection: None,
+            scan: None,
+            duration: Default::default(),
             last_update: None,
+            failing_since: None,
         }
     }
 
@@ -27,8 +92,27 @@ impl EndpointState {
             endpoint,
             target: Some(target),
             cert_idents: Default::default(),
+            leaf: None,
             probe_result: Ok(()),
+            verification: None,
+            ocsp_stapled: false,
+            ocsp: None,
+            crls: Vec::new(),
+            ct_compliant: None,
+            connection: None,
+            scan: None,
+            duration: Default::default(),
             last_update: None,
+            failing_since: None,
         }
     }
+
+    /// Whether the endpoint has been unreachable for at least `ttl`.
+    ///
+    /// A healthy endpoint is never stale, however long ago it was probed.
+    pub fn is_stale(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        self.failing_since
+            .and_then(|since| (now - since).to_std().ok())
+            .map_or(false, |unreachable| unreachable >= ttl)
+    }
 }
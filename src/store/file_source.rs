@@ -0,0 +1,129 @@
+use crate::{
+    cert::CertificateIdentifier,
+    configs::{KeystorePassword, SchedulerOverrideConfig},
+    error::{AppError, ErrorReason},
+};
+use anyhow::Result as AnyResult;
+use chrono::{DateTime, Utc};
+use glob::Pattern;
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// A file, a directory, or a glob pattern of the local certificates to monitor
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FileSource {
+    pub pattern: String,
+}
+
+impl FileSource {
+    /// Read the files matching the pattern. The files in a matched directory are read as well,
+    /// but not the ones in its subdirectories.
+    ///
+    /// Files and directories which can't be read are skipped.
+    pub async fn read_files(&self) -> AnyResult<Vec<(PathBuf, Vec<u8>)>> {
+        // Walking the file system blocks, e.g. on a slow network mount
+        let pattern = self.pattern.clone();
+        let paths = tokio::task::spawn_blocking(move || Self::find_files(&pattern)).await??;
+
+        let mut files = Vec::new();
+        for path in paths {
+            match tokio::fs::read(&path).await {
+                Ok(data) => files.push((path, data)),
+                Err(e) => warn!("Failed to read {}: {}", path.display(), e),
+            }
+        }
+        Ok(files)
+    }
+
+    fn find_files(pattern: &str) -> AnyResult<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in glob::glob(pattern)? {
+            let path = match entry {
+                Ok(path) => path,
+                Err(e) => {
+                    warn!("Failed to read {}: {}", e.path().display(), e.error());
+                    continue;
+                }
+            };
+            if !path.is_dir() {
+                paths.push(path);
+                continue;
+            }
+
+            let directory = match std::fs::read_dir(&path) {
+                Ok(directory) => directory,
+                Err(e) => {
+                    warn!("Failed to read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for entry in directory {
+                let path = match entry {
+                    Ok(entry) => entry.path(),
+                    Err(e) => {
+                        warn!("Failed to read {}: {}", path.display(), e);
+                        break;
+                    }
+                };
+                // Follow the symbolic links, e.g. the hashed names in /etc/ssl/certs
+                match std::fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => paths.push(path),
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to read {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
+
+impl FromStr for FileSource {
+    type Err = AppError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Pattern::new(pattern).map_err(ErrorReason::from)?;
+        Ok(Self {
+            pattern: pattern.to_owned(),
+        })
+    }
+}
+
+impl Display for FileSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FileSourceState {
+    pub schedule_config: SchedulerOverrideConfig,
+    pub password: KeystorePassword,
+    pub last_read: Option<DateTime<Utc>>,
+    pub next_read: Option<DateTime<Utc>>,
+}
+
+/// The certificates read from a file
+#[derive(Clone, Debug)]
+pub struct FileState {
+    pub entries: Vec<FileEntry>,
+}
+
+/// An entry of a keystore, or all the certificates of another file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Empty if the file isn't a keystore
+    pub alias: String,
+    pub cert_idents: Vec<CertificateIdentifier>,
+}
+
+impl From<Vec<CertificateIdentifier>> for FileEntry {
+    fn from(cert_idents: Vec<CertificateIdentifier>) -> Self {
+        Self {
+            alias: String::new(),
+            cert_idents,
+        }
+    }
+}
@@ -1,18 +1,28 @@
 use crate::{
-    cert::{CertificateIdentifier, ParsedCertificate},
-    prober::ProbeResult,
+    cert::{CertificateIdentifier, CertificateRevocationList, ParsedCertificate},
+    configs::{ConnectionParameters, StoreConfig},
+    keystore::{Keystore, KeystoreFormat},
+    prober::{ProbeError, ProbeResult},
+};
+use anyhow::Result as AnyResult;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
 };
-use anyhow::{Context, Result as AnyResult};
-use chrono::Utc;
-use std::collections::HashMap;
 use x509_certificate::X509Certificate;
 
+mod configured_certs;
 mod endpoint;
 mod endpoint_state;
+mod file_source;
 mod target;
 
+pub use configured_certs::{CertificateSource, ConfiguredCertificates};
 pub use endpoint::Endpoint;
-pub use endpoint_state::EndpointState;
+pub use endpoint_state::{EndpointState, LeafDetails};
+pub use file_source::{FileEntry, FileSource, FileSourceState, FileState};
 pub use target::{Target, TargetState};
 
 #[derive(Clone, Debug, Default)]
@@ -20,18 +30,34 @@ pub struct Store {
     //pub target_store: HashMap<Target, TargetState>,
     pub endpoint_store: HashMap<Endpoint, EndpointState>,
     pub cert_store: HashMap<CertificateIdentifier, ParsedCertificate>,
+    /// Targets which couldn't be probed at all, e.g. failed to resolve
+    pub target_errors: HashMap<Target, ProbeError>,
+    /// Certificates read from local files, by their source since the sources may overlap
+    pub file_store: HashMap<(FileSource, PathBuf), FileState>,
+    /// Trust anchors and client certificates of the configuration by target, or `None` for the
+    /// global ones
+    pub configured_certs: HashMap<Option<Target>, Vec<ConfiguredCertificates>>,
+    /// CRLs of the configuration by target, or `None` for the global ones
+    pub configured_crls: HashMap<Option<Target>, Vec<Arc<CertificateRevocationList>>>,
+    /// When the evicted endpoints started failing, so that an endpoint which keeps failing is not
+    /// exported again until it recovers
+    evicted_endpoints: HashMap<Endpoint, (Option<Target>, DateTime<Utc>)>,
+    config: StoreConfig,
 }
 
 impl Store {
-    pub fn add_pem_certificates(&mut self, buf: &[u8]) -> AnyResult<()> {
+    pub fn new(config: StoreConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_pem_certificates(&mut self, buf: &[u8]) -> AnyResult<Vec<CertificateIdentifier>> {
         let certificates = X509Certificate::from_pem_multiple(buf)?
             .into_iter()
             .map(ParsedCertificate);
-        for cert in certificates {
-            let identifier = cert.certificate_identifier()?;
-            self.cert_store.entry(identifier).or_insert(cert);
-        }
-        Ok(())
+        self.add_certificates(certificates)
     }
 
     pub fn add_certificates(
@@ -65,28 +91,639 @@ impl Store {
         let ep_states: Vec<EndpointState> = probe_results
             .into_iter()
             .map(|probe| {
+                let leaf = probe
+                    .certificates
+                    .first()
+                    .map(|leaf| LeafDetails::parse(leaf, &probe.endpoint));
                 self.add_certificates(probe.certificates)
                     .map(|cert_idents| EndpointState {
                         endpoint: probe.endpoint,
                         cert_idents,
+                        leaf,
                         probe_result: probe.probe_result,
+                        verification: probe.verification,
+                        ocsp_stapled: probe.ocsp_response.is_some(),
+                        ocsp: probe.ocsp,
+                        crls: probe.crls,
+                        ct_compliant: probe.ct_compliant,
+                        connection: probe.connection,
+                        scan: probe.scan,
+                        duration: probe.duration,
                         target: Some(target.clone()),
                         last_update: Some(Utc::now()),
+                        failing_since: None,
                     })
             })
             .collect::<AnyResult<_>>()?;
 
+        self.target_errors.remove(target);
         self.update_endpoints(target, ep_states);
+        self.evict_stale();
         Ok(())
     }
 
-    fn update_endpoints(&mut self, target: &Target, ep_states: Vec<EndpointState>) {
+    pub fn update_probe_error(&mut self, target: &Target, error: ProbeError) {
+        // The endpoints of the target are no longer probed, so they are unreachable from now on
+        let now = Utc::now();
+        for ep in self.endpoint_store.values_mut() {
+            if ep.target.as_ref() == Some(target) {
+                ep.probe_result = Err(error.clone());
+                ep.failing_since.get_or_insert(now);
+            }
+        }
+
+        self.target_errors.insert(target.clone(), error);
+        self.evict_stale();
+    }
+
+    /// Forget everything known about a target, e.g. when it has been removed from the configuration.
+    pub fn remove_target(&mut self, target: &Target) {
         self.endpoint_store
-            .extend(ep_states.into_iter().map(|ep| (ep.endpoint.clone(), ep)));
+            .retain(|_, ep| ep.target.as_ref() != Some(target));
+        self.evicted_endpoints
+            .retain(|_, (ep_target, _)| ep_target.as_ref() != Some(target));
+        self.target_errors.remove(target);
+        self.configured_certs.remove(&Some(target.clone()));
+        self.configured_crls.remove(&Some(target.clone()));
+        self.collect_garbage();
+    }
+
+    /// Replace the trust anchors, client certificates and CRLs configured for the target, or the
+    /// global ones if `target` is `None`.
+    pub fn update_configured_certificates(
+        &mut self,
+        target: Option<&Target>,
+        conn_params: &ConnectionParameters,
+    ) -> AnyResult<()> {
+        let ca_source = if target.is_some() {
+            CertificateSource::TargetCa
+        } else {
+            CertificateSource::GlobalAnchor
+        };
+
+        let mut configured = Vec::new();
+        for (source, certs) in [
+            (ca_source, &conn_params.ca_certs),
+            (CertificateSource::ClientCert, &conn_params.certs),
+        ] {
+            if certs.is_empty() {
+                continue;
+            }
+            let certificates = certs
+                .iter()
+                .map(|der| X509Certificate::from_der(der).map(ParsedCertificate))
+                .collect::<Result<Vec<_>, _>>()?;
+            configured.push(ConfiguredCertificates {
+                source,
+                cert_idents: self.add_certificates(certificates)?,
+            });
+        }
+
+        let target = target.cloned();
+        if conn_params.crls.is_empty() {
+            self.configured_crls.remove(&target);
+        } else {
+            self.configured_crls
+                .insert(target.clone(), conn_params.crls.clone());
+        }
+        if configured.is_empty() {
+            self.configured_certs.remove(&target);
+        } else {
+            self.configured_certs.insert(target, configured);
+        }
+        self.collect_garbage();
+        Ok(())
+    }
+
+    /// Replace the certificates of the source with the ones in the files read this time.
+    ///
+    /// Files which don't contain PEM encoded certificates are parsed as a DER certificate.
+    pub fn update_file_source(
+        &mut self,
+        source: &FileSource,
+        files: Vec<(PathBuf, Vec<u8>)>,
+        password: Option<&str>,
+    ) {
+        let mut file_states = HashMap::new();
+        for (path, data) in files {
+            let entries = if KeystoreFormat::detect(&data).is_some() {
+                self.add_keystore_certificates(&data, password)
+            } else if data.windows(11).any(|window| window == b"-----BEGIN ") {
+                self.add_pem_certificates(&data)
+                    .map(|cert_idents| vec![FileEntry::from(cert_idents)])
+            } else {
+                X509Certificate::from_der(&data)
+                    .map_err(Into::into)
+                    .and_then(|cert| self.add_certificates([ParsedCertificate(cert)]))
+                    .map(|cert_idents| vec![FileEntry::from(cert_idents)])
+            };
+            match entries {
+                Ok(entries) => {
+                    file_states.insert((source.clone(), path), FileState { entries });
+                }
+                Err(e) => warn!(
+                    "Failed to parse the certificates in {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        self.file_store
+            .retain(|(file_source, _), _| file_source != source);
+        self.file_store.extend(file_states);
+        self.collect_garbage();
+    }
+
+    fn add_keystore_certificates(
+        &mut self,
+        data: &[u8],
+        password: Option<&str>,
+    ) -> AnyResult<Vec<FileEntry>> {
+        Keystore::parse(data, password)?
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let certificates = entry
+                    .certificates
+                    .iter()
+                    .map(|der| X509Certificate::from_der(der).map(ParsedCertificate))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(FileEntry {
+                    alias: entry.alias,
+                    cert_idents: self.add_certificates(certificates)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Forget the certificates of a source which has been removed from the configuration.
+    pub fn remove_file_source(&mut self, source: &FileSource) {
+        self.file_store
+            .retain(|(file_source, _), _| file_source != source);
+        self.collect_garbage();
+    }
+
+    /// Replace the endpoints of the target with the latest probed ones.
+    ///
+    /// Endpoints which were not probed this time, e.g. because the DNS records changed, are
+    /// removed.
+    fn update_endpoints(&mut self, target: &Target, ep_states: Vec<EndpointState>) {
+        let mut ep_states: HashMap<Endpoint, EndpointState> = ep_states
+            .into_iter()
+            .map(|ep| (ep.endpoint.clone(), ep))
+            .collect();
+
+        for ep in ep_states.values_mut() {
+            if ep.probe_result.is_err() {
+                ep.failing_since = self
+                    .endpoint_store
+                    .get(&ep.endpoint)
+                    .and_then(|old| old.failing_since)
+                    .or_else(|| {
+                        self.evicted_endpoints
+                            .get(&ep.endpoint)
+                            .map(|(_, failing_since)| *failing_since)
+                    })
+                    .or(ep.last_update);
+            }
+        }
+
+        self.endpoint_store.retain(|endpoint, ep| {
+            ep.target.as_ref() != Some(target) || ep_states.contains_key(endpoint)
+        });
+        // Forget the evicted endpoints which recovered or are no longer resolved
+        self.evicted_endpoints.retain(|endpoint, (ep_target, _)| {
+            ep_target.as_ref() != Some(target)
+                || ep_states
+                    .get(endpoint)
+                    .map_or(false, |ep| ep.probe_result.is_err())
+        });
+        self.endpoint_store.extend(ep_states);
+    }
+
+    /// Drop the endpoints which have been unreachable for longer than the TTL, and the
+    /// certificates which are no longer used.
+    fn evict_stale(&mut self) {
+        let now = Utc::now();
+        let ttl = self.config.stale_ttl;
+        let evicted_endpoints = &mut self.evicted_endpoints;
+        self.endpoint_store.retain(|endpoint, ep| {
+            if !ep.is_stale(now, ttl) {
+                return true;
+            }
+            if let Some(failing_since) = ep.failing_since {
+                if evicted_endpoints
+                    .insert(endpoint.clone(), (ep.target.clone(), failing_since))
+                    .is_none()
+                {
+                    info!("Evict stale endpoint: {}", endpoint);
+                }
+            }
+            false
+        });
+        self.collect_garbage();
+    }
+
+    /// Drop the certificates which are not referenced by any endpoint, file or configuration.
+    fn collect_garbage(&mut self) {
+        let referenced: HashSet<&CertificateIdentifier> = self
+            .endpoint_store
+            .values()
+            .flat_map(|ep| &ep.cert_idents)
+            .chain(
+                self.file_store
+                    .values()
+                    .flat_map(|file| &file.entries)
+                    .flat_map(|entry| &entry.cert_idents),
+            )
+            .chain(
+                self.configured_certs
+                    .values()
+                    .flatten()
+                    .flat_map(|configured| &configured.cert_idents),
+            )
+            .collect();
+        self.cert_store
+            .retain(|identifier, _| referenced.contains(identifier));
     }
 
     pub fn clear(&mut self) {
         self.cert_store.clear();
         self.endpoint_store.clear();
+        self.target_errors.clear();
+        self.evicted_endpoints.clear();
+        self.file_store.clear();
+        self.configured_certs.clear();
+        self.configured_crls.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::ErrorClass,
+        testing::{endpoint, parsed_certificate, probe_result},
+    };
+    use rustls_pki_types::CertificateDer;
+    use std::time::Duration;
+
+    fn probe_error() -> ProbeError {
+        ProbeError {
+            class: ErrorClass::Refused,
+            message: "Connection refused".to_owned(),
+        }
+    }
+
+    #[test]
+    fn evict_unprobed_endpoints() {
+        let target: Target = "example.com:443".parse().unwrap();
+        let old_cert = parsed_certificate();
+        let new_cert = parsed_certificate();
+        let mut store = Store::default();
+
+        store
+            .update_probe_result(
+                &target,
+                vec![
+                    probe_result(endpoint("192.0.2.1:443"), vec![old_cert], Ok(())),
+                    probe_result(endpoint("192.0.2.2:443"), vec![new_cert.clone()], Ok(())),
+                ],
+            )
+            .unwrap();
+        assert_eq!(store.endpoint_store.len(), 2);
+        assert_eq!(store.cert_store.len(), 2);
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.2:443"),
+                    vec![new_cert.clone()],
+                    Ok(()),
+                )],
+            )
+            .unwrap();
+        assert!(store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.2:443")));
+        assert_eq!(store.endpoint_store.len(), 1);
+        assert_eq!(
+            store.cert_store.keys().collect::<Vec<_>>(),
+            [&new_cert.certificate_identifier().unwrap()]
+        );
+    }
+
+    #[test]
+    fn keep_failing_since() {
+        let target: Target = "example.com:443".parse().unwrap();
+        let mut store = Store::default();
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    vec![],
+                    Err(probe_error()),
+                )],
+            )
+            .unwrap();
+        let failing_since = store.endpoint_store[&endpoint("192.0.2.1:443")].failing_since;
+        assert!(failing_since.is_some());
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    vec![],
+                    Err(probe_error()),
+                )],
+            )
+            .unwrap();
+        assert_eq!(
+            store.endpoint_store[&endpoint("192.0.2.1:443")].failing_since,
+            failing_since
+        );
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(endpoint("192.0.2.1:443"), vec![], Ok(()))],
+            )
+            .unwrap();
+        assert!(store.endpoint_store[&endpoint("192.0.2.1:443")]
+            .failing_since
+            .is_none());
+    }
+
+    #[test]
+    fn keep_endpoints_of_each_server_name() {
+        let target: Target = "192.0.2.1:443".parse().unwrap();
+        let named = |server_name: &str| Endpoint {
+            sockaddr: "192.0.2.1:443".parse().unwrap(),
+            server_name: server_name.to_owned().try_into().unwrap(),
+        };
+        let mut store = Store::default();
+
+        store
+            .update_probe_result(
+                &target,
+                vec![
+                    probe_result(named("a.example"), vec![parsed_certificate()], Ok(())),
+                    probe_result(named("b.example"), vec![parsed_certificate()], Ok(())),
+                ],
+            )
+            .unwrap();
+        assert_eq!(store.endpoint_store.len(), 2);
+        assert_eq!(store.cert_store.len(), 2);
+
+        store
+            .update_probe_result(
+                &target,
+                vec![
+                    probe_result(named("a.example"), vec![parsed_certificate()], Ok(())),
+                    probe_result(named("b.example"), vec![], Err(probe_error())),
+                ],
+            )
+            .unwrap();
+        assert_eq!(store.endpoint_store.len(), 2);
+        assert!(store.endpoint_store[&named("a.example")]
+            .probe_result
+            .is_ok());
+        assert!(store.endpoint_store[&named("b.example")]
+            .failing_since
+            .is_some());
+    }
+
+    #[test]
+    fn evict_stale_endpoints() {
+        let target: Target = "example.com:443".parse().unwrap();
+        let other: Target = "example.net:443".parse().unwrap();
+        let mut store = Store::new(StoreConfig {
+            stale_ttl: Duration::from_secs(3600),
+        });
+
+        store
+            .update_probe_result(
+                &target,
+                vec![
+                    probe_result(
+                        endpoint("192.0.2.1:443"),
+                        vec![parsed_certificate()],
+                        Ok(()),
+                    ),
+                    probe_result(endpoint("192.0.2.2:443"), vec![], Err(probe_error())),
+                ],
+            )
+            .unwrap();
+        let ep_state = store
+            .endpoint_store
+            .get_mut(&endpoint("192.0.2.2:443"))
+            .unwrap();
+        ep_state.failing_since = Some(Utc::now() - chrono::Duration::hours(2));
+
+        store.update_probe_error(&other, probe_error());
+        assert!(store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.1:443")));
+        assert!(!store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.2:443")));
+
+        // The endpoint which keeps failing is not exported again
+        store
+            .update_probe_result(
+                &target,
+                vec![
+                    probe_result(
+                        endpoint("192.0.2.1:443"),
+                        vec![parsed_certificate()],
+                        Ok(()),
+                    ),
+                    probe_result(endpoint("192.0.2.2:443"), vec![], Err(probe_error())),
+                ],
+            )
+            .unwrap();
+        assert_eq!(store.endpoint_store.len(), 1);
+
+        // The target can't be resolved anymore
+        store.update_probe_error(&target, probe_error());
+        assert_eq!(store.endpoint_store.len(), 1);
+        let ep_state = store
+            .endpoint_store
+            .get_mut(&endpoint("192.0.2.1:443"))
+            .unwrap();
+        ep_state.failing_since = Some(Utc::now() - chrono::Duration::hours(2));
+
+        store.update_probe_error(&target, probe_error());
+        assert!(store.endpoint_store.is_empty());
+        assert!(store.cert_store.is_empty());
+
+        // The endpoint recovered
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(endpoint("192.0.2.2:443"), vec![], Ok(()))],
+            )
+            .unwrap();
+        assert!(store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.2:443")));
+        assert!(store.evicted_endpoints.is_empty());
+    }
+
+    #[test]
+    fn keep_endpoints_probed_less_often_than_ttl() {
+        let target: Target = "example.com:443".parse().unwrap();
+        let other: Target = "example.net:443".parse().unwrap();
+        let mut store = Store::new(StoreConfig {
+            stale_ttl: Duration::from_secs(3600),
+        });
+
+        store
+            .update_probe_result(
+                &target,
+                vec![probe_result(
+                    endpoint("192.0.2.1:443"),
+                    vec![parsed_certificate()],
+                    Ok(()),
+                )],
+            )
+            .unwrap();
+        // The interval of the target is longer than the TTL
+        store
+            .endpoint_store
+            .get_mut(&endpoint("192.0.2.1:443"))
+            .unwrap()
+            .last_update = Some(Utc::now() - chrono::Duration::hours(2));
+
+        store
+            .update_probe_result(
+                &other,
+                vec![probe_result(endpoint("192.0.2.2:443"), vec![], Ok(()))],
+            )
+            .unwrap();
+        store.update_probe_error(&other, probe_error());
+        assert!(store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.1:443")));
+        assert_eq!(store.cert_store.len(), 1);
+
+        // The first failure of the resolution doesn't evict the endpoint either
+        store.update_probe_error(&target, probe_error());
+        assert!(store
+            .endpoint_store
+            .contains_key(&endpoint("192.0.2.1:443")));
+    }
+
+    #[test]
+    fn update_file_source() {
+        let source: FileSource = "/etc/ssl/*.pem".parse().unwrap();
+        let mut store = Store::default();
+
+        let (leaf, chain) = (parsed_certificate(), parsed_certificate());
+        let bundle = leaf.encode_pem().unwrap() + &chain.encode_pem().unwrap();
+        store.update_file_source(
+            &source,
+            vec![
+                (PathBuf::from("/etc/ssl/bundle.pem"), bundle.into_bytes()),
+                (
+                    PathBuf::from("/etc/ssl/leaf.der"),
+                    leaf.encode_der().unwrap(),
+                ),
+                (PathBuf::from("/etc/ssl/broken.der"), vec![0x30, 0x00]),
+            ],
+            None,
+        );
+        assert_eq!(store.file_store.len(), 2);
+        assert_eq!(
+            store.file_store[&(source.clone(), PathBuf::from("/etc/ssl/bundle.pem"))].entries,
+            [FileEntry::from(vec![
+                leaf.certificate_identifier().unwrap(),
+                chain.certificate_identifier().unwrap()
+            ])]
+        );
+        assert_eq!(store.cert_store.len(), 2);
+
+        // The certificates of the files are not referenced by any endpoint
+        let target: Target = "example.com:443".parse().unwrap();
+        store.update_probe_error(&target, probe_error());
+        assert_eq!(store.cert_store.len(), 2);
+
+        store.update_file_source(&source, vec![], None);
+        assert!(store.file_store.is_empty());
+        assert!(store.cert_store.is_empty());
+    }
+
+    #[test]
+    fn overlapping_file_sources() {
+        let directory: FileSource = "/etc/ssl".parse().unwrap();
+        let pattern: FileSource = "/etc/ssl/*.pem".parse().unwrap();
+        let mut store = Store::default();
+
+        let cert = parsed_certificate();
+        let files = vec![(
+            PathBuf::from("/etc/ssl/cert.pem"),
+            cert.encode_pem().unwrap().into_bytes(),
+        )];
+        store.update_file_source(&directory, files.clone(), None);
+        store.update_file_source(&pattern, files, None);
+        assert_eq!(store.file_store.len(), 2);
+
+        // The file is still matched by the other source
+        store.remove_file_source(&directory);
+        assert_eq!(store.file_store.len(), 1);
+        assert_eq!(store.cert_store.len(), 1);
+    }
+
+    #[test]
+    fn update_configured_certificates() {
+        let target: Target = "example.com:443".parse().unwrap();
+        let mut store = Store::default();
+        let der = |cert: &ParsedCertificate| CertificateDer::from(cert.encode_der().unwrap());
+
+        let (ca, client) = (parsed_certificate(), parsed_certificate());
+        let conn_params = ConnectionParameters {
+            ca_certs: vec![der(&ca)],
+            certs: vec![der(&client)],
+            ..Default::default()
+        };
+        store
+            .update_configured_certificates(Some(&target), &conn_params)
+            .unwrap();
+        assert_eq!(
+            store.configured_certs[&Some(target.clone())],
+            [
+                ConfiguredCertificates {
+                    source: CertificateSource::TargetCa,
+                    cert_idents: vec![ca.certificate_identifier().unwrap()],
+                },
+                ConfiguredCertificates {
+                    source: CertificateSource::ClientCert,
+                    cert_idents: vec![client.certificate_identifier().unwrap()],
+                },
+            ]
+        );
+
+        let global_params = ConnectionParameters {
+            ca_certs: vec![der(&ca)],
+            ..Default::default()
+        };
+        store
+            .update_configured_certificates(None, &global_params)
+            .unwrap();
+        assert_eq!(
+            store.configured_certs[&None][0].source,
+            CertificateSource::GlobalAnchor
+        );
+        assert_eq!(store.cert_store.len(), 2);
+
+        // The CA is still a global anchor
+        store.remove_target(&target);
+        assert_eq!(store.configured_certs.len(), 1);
+        assert_eq!(store.cert_store.len(), 1);
     }
 }
@@ -0,0 +1,278 @@
+//! Helpers shared by the tests of several modules
+
+use crate::{
+    cert::ParsedCertificate,
+    prober::{ProbeError, ProbeResult},
+    store::Endpoint,
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use prometheus::proto::{Metric, MetricFamily};
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    time::sleep,
+};
+use tokio_rustls::{
+    rustls::{crypto::ring::default_provider, ServerConfig},
+    TlsAcceptor,
+};
+use x509_certificate::{KeyAlgorithm, X509CertificateBuilder};
+
+/// An endpoint of `example.com` at the address
+pub fn endpoint(addr: &str) -> Endpoint {
+    Endpoint {
+        sockaddr: addr.parse().unwrap(),
+        server_name: "example.com".try_into().unwrap(),
+    }
+}
+
+/// A self-signed certificate, which is only parsed and never verified
+pub fn parsed_certificate() -> ParsedCertificate {
+    let (cert, _) = X509CertificateBuilder::default()
+        .create_with_random_keypair(KeyAlgorithm::Ed25519)
+        .unwrap();
+    ParsedCertificate((*cert).clone())
+}
+
+/// The result of probing the endpoint, without any details besides the certificates
+pub fn probe_result(
+    endpoint: Endpoint,
+    certificates: Vec<ParsedCertificate>,
+    probe_result: Result<(), ProbeError>,
+) -> ProbeResult {
+    ProbeResult {
+        endpoint,
+        certificates,
+        probe_result,
+        verification: None,
+        ocsp_response: None,
+        ocsp: None,
+        crls: Vec::new(),
+        ct_compliant: None,
+        connection: None,
+        scan: None,
+        duration: Default::default(),
+    }
+}
+
+/// The series of the family with the name, or none if the family is missing
+pub fn series<'a>(metric_families: &'a [MetricFamily], name: &str) -> &'a [Metric] {
+    metric_families
+        .iter()
+        .find(|family| family.get_name() == name)
+        .map_or(&[], |family| family.get_metric())
+}
+
+/// The value of the label of a series
+pub fn label<'a>(metric: &'a Metric, name: &str) -> &'a str {
+    metric
+        .get_label()
+        .iter()
+        .find(|label| label.get_name() == name)
+        .unwrap()
+        .get_value()
+}
+
+/// ecdsa-with-SHA256, whose parameters are absent
+const ECDSA_SHA256: &[u8] = &[
+    0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02,
+];
+/// id-ecPublicKey with the named curve prime256v1
+const EC_P256: &[u8] = &[
+    0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x08, 0x2A, 0x86, 0x48,
+    0xCE, 0x3D, 0x03, 0x01, 0x07,
+];
+/// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 85, 4, 3];
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 85, 29, 17];
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut encoded = match len {
+        0..=0x7F => vec![tag, len as u8],
+        0x80..=0xFF => vec![tag, 0x81, len as u8],
+        _ => vec![tag, 0x82, (len >> 8) as u8, len as u8],
+    };
+    encoded.extend(content);
+    encoded
+}
+
+/// A self-signed certificate with an ECDSA P-256 key for the given DNS names and IP addresses,
+/// which is valid for a day until `not_after`.
+///
+/// The certificate is encoded by hand, since x509-certificate adds NULL parameters to the
+/// ECDSA signature algorithm, which webpki rejects.
+pub fn certificate(
+    subject_alt_names: &[&str],
+    not_after: DateTime<Utc>,
+) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+    let key_pair =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+
+    // A distinct name, so that the certificate isn't mistaken for the issuer of another one
+    let common_name: String = key_pair.public_key().as_ref()[1..9]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let name = der(
+        0x30,
+        &der(
+            0x31,
+            &der(
+                0x30,
+                &[OID_COMMON_NAME, &der(0x0C, common_name.as_bytes())].concat(),
+            ),
+        ),
+    );
+    let time = |time: DateTime<Utc>| der(0x17, time.format("%y%m%d%H%M%SZ").to_string().as_bytes());
+    let mut tbs = [
+        der(0xA0, &der(0x02, &[2])),
+        der(0x02, &[1]),
+        ECDSA_SHA256.to_vec(),
+        name.clone(),
+        der(
+            0x30,
+            &[time(not_after - ChronoDuration::days(1)), time(not_after)].concat(),
+        ),
+        name,
+        der(
+            0x30,
+            &[
+                EC_P256,
+                &der(0x03, &[&[0], key_pair.public_key().as_ref()].concat()),
+            ]
+            .concat(),
+        ),
+    ]
+    .concat();
+    if !subject_alt_names.is_empty() {
+        let names: Vec<u8> = subject_alt_names
+            .iter()
+            .flat_map(|name| match name.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => der(0x87, &ip.octets()),
+                Ok(IpAddr::V6(ip)) => der(0x87, &ip.octets()),
+                Err(_) => der(0x82, name.as_bytes()),
+            })
+            .collect();
+        let extension = [OID_SUBJECT_ALT_NAME, &der(0x04, &der(0x30, &names))].concat();
+        tbs.extend(der(0xA3, &der(0x30, &der(0x30, &extension))));
+    }
+    let tbs = der(0x30, &tbs);
+
+    let signature = key_pair.sign(&rng, &tbs).unwrap();
+    let cert = der(
+        0x30,
+        &[
+            tbs.as_slice(),
+            ECDSA_SHA256,
+            &der(0x03, &[&[0], signature.as_ref()].concat()),
+        ]
+        .concat(),
+    );
+    (
+        CertificateDer::from(cert),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec())),
+    )
+}
+
+/// A local TLS server with a self-signed certificate.
+///
+/// It accepts `handshakes` connections, and the following ones are kept open without a
+/// response.
+pub struct TlsServer {
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// Negotiate SMTP STARTTLS first, waiting that long before accepting STARTTLS and again
+    /// before the handshake
+    pub smtp_delay: Option<Duration>,
+    pub subject_alt_names: Vec<&'static str>,
+    pub not_after: DateTime<Utc>,
+    pub handshakes: usize,
+}
+
+impl Default for TlsServer {
+    fn default() -> Self {
+        Self {
+            alpn_protocols: Vec::new(),
+            smtp_delay: None,
+            subject_alt_names: Vec::new(),
+            not_after: Utc::now() + ChronoDuration::hours(1),
+            handshakes: 1,
+        }
+    }
+}
+
+pub struct RunningTlsServer {
+    pub addr: SocketAddr,
+    pub cert: CertificateDer<'static>,
+    /// The SNI of each completed handshake
+    pub server_names: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl TlsServer {
+    pub async fn start(self) -> RunningTlsServer {
+        let (cert, key) = certificate(&self.subject_alt_names, self.not_after);
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        config.alpn_protocols = self.alpn_protocols;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let smtp_delay = self.smtp_delay;
+        let server_names = Arc::new(Mutex::new(Vec::new()));
+        let recorded_names = server_names.clone();
+        tokio::spawn(async move {
+            for _ in 0..self.handshakes {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                if let Some(delay) = smtp_delay {
+                    let mut conn = BufReader::new(&mut stream);
+                    let mut line = String::new();
+                    conn.write_all(b"220 ready\r\n").await.unwrap();
+                    conn.read_line(&mut line).await.unwrap();
+                    conn.write_all(b"250-localhost\r\n250 STARTTLS\r\n")
+                        .await
+                        .unwrap();
+                    conn.read_line(&mut line).await.unwrap();
+                    sleep(delay).await;
+                    conn.write_all(b"220 go ahead\r\n").await.unwrap();
+                    sleep(delay).await;
+                }
+                // The client may have given up already
+                if let Ok(tls) = acceptor.accept(stream).await {
+                    let server_name = tls.get_ref().1.server_name().map(str::to_owned);
+                    recorded_names.lock().unwrap().push(server_name);
+                }
+            }
+
+            let mut idle = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                idle.push(stream);
+            }
+        });
+
+        RunningTlsServer {
+            addr,
+            cert,
+            server_names,
+        }
+    }
+}